@@ -0,0 +1,145 @@
+// Thumbnail throughput of the regular path (`image` full decode and resize) against the fast
+// path (DCT-scaled JPEG decode and `fast_image_resize`).
+//
+//     cargo bench --bench thumbnail_decode [-- <directory of JPEGs>]
+//
+// Without a directory, a corpus of synthetic camera-sized JPEGs is generated in the temp dir.
+#[path = "../src/fast_path.rs"]
+mod fast_path;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, RgbImage};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+// The default thumbnail profile's size
+const THUMBNAIL_SIZE: u32 = 512;
+const ROUNDS: u32 = 3;
+const FIXTURE_SIZES: [(u32, u32); 4] = [(6000, 4000), (4032, 3024), (3000, 2000), (1920, 1080)];
+const FIXTURES_PER_SIZE: u32 = 3;
+
+fn main() {
+    // `cargo bench` passes `--bench` through to benches without the libtest harness
+    let corpus = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from)
+        .unwrap_or_else(generate_corpus);
+    let images = load_corpus(&corpus);
+    if images.is_empty() {
+        eprintln!("No JPEGs in {}", corpus.display());
+        std::process::exit(1);
+    }
+    let megapixels: f64 = images
+        .iter()
+        .filter_map(|data| image::load_from_memory(data).ok())
+        .map(|img| img.width() as f64 * img.height() as f64 / 1e6)
+        .sum();
+    println!("{} JPEGs ({:.1} MP) from {}", images.len(), megapixels, corpus.display());
+
+    let regular = run("image decode + resize", &images, megapixels, regular_thumbnail);
+    let fast = run("DCT-scaled decode + fast_image_resize", &images, megapixels, fast_thumbnail);
+    println!("speedup: {:.2}x", regular.as_secs_f64() / fast.as_secs_f64());
+}
+
+/// Best of `ROUNDS` passes over the corpus, after a warm-up pass.
+fn run(name: &str, images: &[Vec<u8>], megapixels: f64, thumbnail: fn(&[u8]) -> DynamicImage) -> Duration {
+    images.iter().for_each(|data| drop(thumbnail(data)));
+    let best = (0..ROUNDS)
+        .map(|_| {
+            let started = Instant::now();
+            for data in images {
+                std::hint::black_box(thumbnail(data));
+            }
+            started.elapsed()
+        })
+        .min()
+        .unwrap_or_default();
+
+    let seconds = best.as_secs_f64();
+    println!(
+        "{:<40} {:>8.1} ms  {:>7.1} images/s  {:>7.1} MP/s",
+        name,
+        seconds * 1000.0,
+        images.len() as f64 / seconds,
+        megapixels / seconds
+    );
+    best
+}
+
+fn regular_thumbnail(data: &[u8]) -> DynamicImage {
+    image::load_from_memory(data)
+        .expect("corpus JPEG decodes")
+        .resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle)
+}
+
+fn fast_thumbnail(data: &[u8]) -> DynamicImage {
+    let mut target = (0, 0);
+    let img = match fast_path::decode_jpeg_scaled(data, |size| {
+        target = fit_within(size);
+        target
+    }) {
+        Some((img, _)) => img,
+        // Sources too small for a scaled decode
+        None => {
+            let img = image::load_from_memory(data).expect("corpus JPEG decodes");
+            target = fit_within((img.width(), img.height()));
+            img
+        }
+    };
+    fast_path::resize(&img, target.0, target.1, FilterType::Triangle).expect("Triangle is supported")
+}
+
+/// The thumbnail size within a `THUMBNAIL_SIZE` box, as `image`'s `resize` computes it.
+fn fit_within((width, height): (u32, u32)) -> (u32, u32) {
+    let ratio = (THUMBNAIL_SIZE as f64 / width as f64).min(THUMBNAIL_SIZE as f64 / height as f64);
+    let scale = |length: u32| ((length as f64 * ratio).round() as u32).max(1);
+    (scale(width), scale(height))
+}
+
+fn load_corpus(dir: &Path) -> Vec<Vec<u8>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", dir.display(), e))
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .map_or(false, |extension| matches!(extension.to_ascii_lowercase().as_str(), "jpg" | "jpeg"))
+        })
+        .collect();
+    paths.sort();
+    paths.iter().filter_map(|path| fs::read(path).ok()).collect()
+}
+
+/// Synthetic photos: smooth gradients with noise, so they compress like real ones. Reused
+/// across runs.
+fn generate_corpus() -> PathBuf {
+    let dir = std::env::temp_dir().join("snaphound-bench-corpus");
+    fs::create_dir_all(&dir).expect("temp dir is writable");
+
+    let mut seed: u32 = 0x9E37_79B9;
+    for (width, height) in FIXTURE_SIZES {
+        for index in 0..FIXTURES_PER_SIZE {
+            let path = dir.join(format!("fixture-{}x{}-{}.jpg", width, height, index));
+            if path.exists() {
+                continue;
+            }
+            let img = RgbImage::from_fn(width, height, |x, y| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let noise = (seed >> 28) as u8;
+                let r = (x * 255 / width) as u8;
+                let g = (y * 255 / height) as u8;
+                let b = ((x + y + index * 97) % 256) as u8;
+                image::Rgb([r.saturating_add(noise), g.saturating_add(noise), b])
+            });
+            let file = fs::File::create(&path).expect("fixture is writable");
+            JpegEncoder::new_with_quality(file, 90)
+                .encode_image(&img)
+                .expect("fixture encodes");
+        }
+    }
+    dir
+}
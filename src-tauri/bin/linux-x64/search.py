@@ -2,6 +2,7 @@ import sys
 import json
 import threading
 import os
+import inspect
 from snaphoundpy import SnapHound
 
 # Handle optional paths argument
@@ -11,9 +12,26 @@ print("Search Value:", input_data.get("search_data", ""))
 print("Priority Paths:", input_data.get("priority_paths", []))
 print("Paths:", input_data.get("path", []))
 print("Index:", input_data.get("index", False))
+print("Exclude:", input_data.get("exclude", {}))
 
 # Initialize SnapHound
-snaphoundpy = SnapHound(paths=input_data.get("path", []), priority_paths=input_data.get("priority_paths", []))
+snaphound_kwargs = {"paths": input_data.get("path", []), "priority_paths": input_data.get("priority_paths", [])}
+snaphound_parameters = inspect.signature(SnapHound).parameters
+
+# Optional settings are only passed on when SnapHoundPy accepts them
+def pass_optional(name, default):
+	if name in snaphound_parameters:
+		snaphound_kwargs[name] = input_data.get(name, default)
+	elif input_data.get(name):
+		print("Warning: this SnapHoundPy version does not support '%s'; ignoring it" % name)
+
+# The same exclusion rules as the Rust scanner
+pass_optional("exclude", {})
+# Per-root max depth, media types and symlink handling
+pass_optional("root_settings", [])
+# HEIC, AVIF, SVG, TIFF, ... on top of SnapHoundPy's defaults
+pass_optional("image_extensions", [])
+snaphoundpy = SnapHound(**snaphound_kwargs)
 print("SnapHound Started.")
 # Global variable to keep track of the indexing thread
 index_thread = None
@@ -33,6 +51,20 @@ def start_indexing():
 			index_thread = threading.Thread(target=index_images_thread, daemon=True)
 			index_thread.start()
 
+# Index or unindex only the given files, as reported by the file watcher
+def index_files(paths):
+	if hasattr(snaphoundpy, "index_files"):
+		snaphoundpy.index_files(paths)
+	else:
+		# Only full passes exist here; the watcher must never trigger a full reindex
+		print("Incremental indexing is not supported by this SnapHoundPy version:", paths)
+
+def unindex_files(paths):
+	if hasattr(snaphoundpy, "unindex_files"):
+		snaphoundpy.unindex_files(paths)
+	else:
+		print("Unindexing is not supported by this SnapHoundPy version:", paths)
+
 def search(search_data):
 	if search_data:
 		if os.path.isfile(search_data):
@@ -52,6 +84,12 @@ def main(data):
 	if input_data["search_data"]:
 		search(input_data["search_data"])
 
+	if data.get("unindex_files"):
+		unindex_files(data["unindex_files"])
+
+	if data.get("index_files"):
+		index_files(data["index_files"])
+
 def server_mode():
 	main(input_data)
 	while True:
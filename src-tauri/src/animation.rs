@@ -0,0 +1,245 @@
+use crate::exif;
+use crate::thumbnail_profile::ThumbnailProfile;
+use image::codecs::gif::GifDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::{AnimationDecoder, DynamicImage, Frames};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use webp::{AnimEncoder, AnimFrame, WebPConfig};
+
+// Long edge of animated previews, and how many frames they keep at most
+const PREVIEW_EDGE: u32 = 256;
+const MAX_PREVIEW_FRAMES: u32 = 48;
+// Browsers play frames with a delay of 10ms or less at 100ms, and so do previews
+const MIN_FRAME_DELAY_MS: u64 = 10;
+const DEFAULT_FRAME_DELAY_MS: u64 = 100;
+
+/// Frame count and total duration of an animated GIF or WebP.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Animation {
+    pub frame_count: u32,
+    pub duration_ms: u64,
+}
+
+/// Detects an animated GIF or WebP from its container structure, without decoding any frames.
+/// Single-frame files are stills.
+pub fn detect(data: &[u8]) -> Option<Animation> {
+    let animation = match data {
+        [b'G', b'I', b'F', ..] => gif_animation(data)?,
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => webp_animation(data),
+        _ => return None,
+    };
+    (animation.frame_count > 1).then_some(animation)
+}
+
+fn frame_delay_ms(delay_ms: u64) -> u64 {
+    if delay_ms <= MIN_FRAME_DELAY_MS {
+        DEFAULT_FRAME_DELAY_MS
+    } else {
+        delay_ms
+    }
+}
+
+/// Walks the GIF's blocks, counting image descriptors and summing the delays of their graphic
+/// control extensions. Truncated files count the frames before the cut.
+fn gif_animation(gif: &[u8]) -> Option<Animation> {
+    // Header and logical screen descriptor, then the optional global color table
+    let flags = *gif.get(10)?;
+    let mut position = 13 + color_table_len(flags);
+    let mut animation = Animation {
+        frame_count: 0,
+        duration_ms: 0,
+    };
+    let mut pending_delay_ms = 0;
+
+    while let Some(&block) = gif.get(position) {
+        match block {
+            // Extension: label, then data sub-blocks
+            0x21 => {
+                let delay = gif.get(position + 4..position + 6);
+                if let (Some(0xF9), Some(delay)) = (gif.get(position + 1), delay) {
+                    pending_delay_ms = u16::from_le_bytes([delay[0], delay[1]]) as u64 * 10;
+                }
+                let Some(next) = skip_sub_blocks(gif, position + 2) else {
+                    break;
+                };
+                position = next;
+            }
+            // Image descriptor, optional local color table, LZW code size, then image data
+            0x2C => {
+                let Some(&flags) = gif.get(position + 9) else {
+                    break;
+                };
+                animation.frame_count += 1;
+                animation.duration_ms += frame_delay_ms(pending_delay_ms);
+                pending_delay_ms = 0;
+                let Some(next) = skip_sub_blocks(gif, position + 10 + color_table_len(flags) + 1) else {
+                    break;
+                };
+                position = next;
+            }
+            // Trailer, or garbage after the last frame
+            _ => break,
+        }
+    }
+    Some(animation)
+}
+
+fn color_table_len(flags: u8) -> usize {
+    if flags & 0x80 == 0 {
+        0
+    } else {
+        3 << ((flags & 0x07) + 1)
+    }
+}
+
+/// Position after a run of data sub-blocks starting at `position`.
+fn skip_sub_blocks(gif: &[u8], mut position: usize) -> Option<usize> {
+    loop {
+        let length = *gif.get(position)? as usize;
+        position += 1 + length;
+        if length == 0 {
+            return Some(position);
+        }
+    }
+}
+
+/// Animated WebPs hold one `ANMF` chunk per frame; its duration is at offset 12 (24-bit).
+fn webp_animation(webp: &[u8]) -> Animation {
+    exif::riff_chunks(webp)
+        .filter(|(id, _)| id == b"ANMF")
+        .filter_map(|(_, frame)| frame.get(12..15))
+        .fold(
+            Animation {
+                frame_count: 0,
+                duration_ms: 0,
+            },
+            |animation, duration| Animation {
+                frame_count: animation.frame_count + 1,
+                duration_ms: animation.duration_ms
+                    + frame_delay_ms(u32::from_le_bytes([duration[0], duration[1], duration[2], 0]) as u64),
+            },
+        )
+}
+
+/// Renders a downscaled animated WebP preview of at most `MAX_PREVIEW_FRAMES` frames, spread
+/// evenly over the source so it keeps its length; skipped frames extend the frame before them.
+/// Frames are resized and adjusted like the profile's thumbnails.
+pub fn render_preview(data: &[u8], animation: Animation, profile: &ThumbnailProfile) -> Result<Vec<u8>, String> {
+    let frames: Frames = match data {
+        [b'G', b'I', b'F', ..] => GifDecoder::new(Cursor::new(data))
+            .map_err(|e| e.to_string())?
+            .into_frames(),
+        _ => WebPDecoder::new(Cursor::new(data))
+            .map_err(|e| e.to_string())?
+            .into_frames(),
+    };
+    let profile = profile.for_level(PREVIEW_EDGE);
+    let budget = MAX_PREVIEW_FRAMES.min(animation.frame_count) as u64;
+
+    let mut kept = Vec::new();
+    let mut last_slot = None;
+    let mut timestamp_ms = 0;
+    for (index, frame) in frames.enumerate() {
+        let frame = frame.map_err(|e| e.to_string())?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        let delay_ms = frame_delay_ms(numerator as u64 / denominator.max(1) as u64);
+
+        let slot = index as u64 * budget / animation.frame_count as u64;
+        if last_slot != Some(slot) && slot < budget {
+            last_slot = Some(slot);
+            kept.push((
+                profile.render_frame(DynamicImage::ImageRgba8(frame.into_buffer())),
+                timestamp_ms,
+            ));
+        }
+        timestamp_ms += delay_ms;
+    }
+
+    let (width, height) = kept
+        .first()
+        .map(|(frame, _)| frame.dimensions())
+        .ok_or_else(|| "Animation has no frames".to_string())?;
+    let mut config = WebPConfig::new().map_err(|_| "Failed to configure WebP encoder".to_string())?;
+    config.quality = profile.quality as f32;
+    let mut encoder = AnimEncoder::new(width, height, &config);
+    for (frame, timestamp_ms) in &kept {
+        encoder.add_frame(AnimFrame::from_rgba(frame.as_raw(), width, height, *timestamp_ms as i32));
+    }
+    encoder
+        .try_encode()
+        .map(|data| data.to_vec())
+        .map_err(|e| format!("Failed to encode animated preview: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A GIF with one frame per delay (in hundredths of a second), each behind a comment and a
+    /// graphic control extension.
+    fn gif(delays: &[u16]) -> Vec<u8> {
+        // Logical screen descriptor with a two-entry global color table
+        let mut gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00".to_vec();
+        gif.extend_from_slice(&[0, 0, 0, 255, 255, 255]);
+        for delay in delays {
+            gif.extend_from_slice(&[0x21, 0xFE, 3, b'h', b'i', b'!', 0]);
+            gif.extend_from_slice(&[0x21, 0xF9, 4, 0]);
+            gif.extend_from_slice(&delay.to_le_bytes());
+            gif.extend_from_slice(&[0, 0]);
+            gif.extend_from_slice(&[0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0, 2, 2, 0x4C, 0x01, 0]);
+        }
+        gif.push(0x3B);
+        gif
+    }
+
+    /// An extended WebP with one `ANMF` chunk per frame duration.
+    fn webp(durations: &[u32]) -> Vec<u8> {
+        let mut chunks = b"VP8X\x0a\x00\x00\x00\x02\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        chunks.extend_from_slice(b"ANIM\x06\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        for duration in durations {
+            // Offset and size (24-bit each), the 24-bit duration, flags, then a stub of frame data
+            chunks.extend_from_slice(b"ANMF\x11\x00\x00\x00");
+            chunks.extend_from_slice(&[0; 12]);
+            chunks.extend_from_slice(&duration.to_le_bytes()[..3]);
+            chunks.extend_from_slice(&[0, 0]);
+            // Odd-sized chunks are padded
+            chunks.push(0);
+        }
+        let mut webp = b"RIFF".to_vec();
+        webp.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        webp.extend_from_slice(b"WEBP");
+        webp.extend(chunks);
+        webp
+    }
+
+    #[test]
+    fn counts_gif_frames_and_delays() {
+        let animation = detect(&gif(&[5, 0, 20])).unwrap();
+        // 50ms, a missing delay played at 100ms, then 200ms
+        assert_eq!((animation.frame_count, animation.duration_ms), (3, 350));
+    }
+
+    #[test]
+    fn single_frame_gifs_are_stills() {
+        assert!(detect(&gif(&[10])).is_none());
+        assert_eq!(gif_animation(&gif(&[10])).unwrap().frame_count, 1);
+    }
+
+    #[test]
+    fn counts_frames_before_a_truncation() {
+        let full = gif(&[10, 10, 10]);
+        let animation = detect(&full[..full.len() - 3]).unwrap();
+        assert_eq!((animation.frame_count, animation.duration_ms), (3, 300));
+        let animation = detect(&full[..full.len() - 30]).unwrap();
+        assert_eq!(animation.frame_count, 2);
+    }
+
+    #[test]
+    fn counts_webp_frames_and_durations() {
+        let animation = detect(&webp(&[40, 0, 1000])).unwrap();
+        assert_eq!((animation.frame_count, animation.duration_ms), (3, 1140));
+        assert!(detect(&webp(&[40])).is_none());
+        assert_eq!(webp_animation(&webp(&[])).frame_count, 0);
+    }
+}
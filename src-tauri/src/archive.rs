@@ -0,0 +1,351 @@
+use crate::catalog::{self, CatalogEntry};
+use crate::initialise::EnvPaths;
+use crate::jobs;
+use crate::media_kind::{self, MediaKind, MediaType};
+use crate::search_processor::send_command_to_python;
+use crate::thumbnail_cache;
+use crate::thumbnail_profile;
+use crate::utils::modified_nanos;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tauri::AppHandle;
+use zip::ZipArchive;
+
+/// Separates an archive's path from a member inside it, as in `comic.cbz!/page_001.jpg`.
+pub const MEMBER_SEPARATOR: &str = "!/";
+
+const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "cbz"];
+// Members are decoded in memory; anything larger is not a page
+const MAX_MEMBER_BYTES: u64 = 256 * 1024 * 1024;
+// Parsed archives kept around, so reading a page doesn't parse the central directory again
+const MAX_CACHED_ARCHIVES: usize = 16;
+
+lazy_static::lazy_static! {
+    static ref ARCHIVES: Mutex<HashMap<PathBuf, Arc<CachedArchive>>> = Mutex::new(HashMap::new());
+}
+
+/// An image member of an archive, as listed by `list_members`.
+pub struct Member {
+    pub path: PathBuf,
+    pub media: MediaType,
+    /// Uncompressed size
+    pub size: u64,
+    /// Modification time (ns) of the archive, see `member_metadata`
+    pub mtime: u64,
+}
+
+/// A parsed archive and the sizes of its files, valid while the archive's own size and
+/// modification time are unchanged.
+struct CachedArchive {
+    size: u64,
+    mtime: u64,
+    opened: Instant,
+    zip: ZipArchive<ArchiveFile>,
+    member_sizes: HashMap<String, u64>,
+}
+
+/// The archive file, opened on first read. A clone opens a handle of its own, so clones of a
+/// cached `ZipArchive` read members in parallel without holding the file open in the cache.
+struct ArchiveFile {
+    path: Arc<PathBuf>,
+    file: Option<BufReader<File>>,
+}
+
+impl ArchiveFile {
+    fn file(&mut self) -> io::Result<&mut BufReader<File>> {
+        if self.file.is_none() {
+            self.file = Some(BufReader::new(File::open(self.path.as_path())?));
+        }
+        Ok(self.file.as_mut().expect("opened above"))
+    }
+}
+
+impl Clone for ArchiveFile {
+    fn clone(&self) -> Self {
+        Self {
+            path: Arc::clone(&self.path),
+            file: None,
+        }
+    }
+}
+
+impl Read for ArchiveFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file()?.read(buf)
+    }
+}
+
+impl Seek for ArchiveFile {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.file()?.seek(position)
+    }
+}
+
+/// ZIP-based archives, browsed as virtual folders of their images.
+pub fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            ARCHIVE_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+}
+
+/// Splits a member path into the archive and the member's name inside it. Directories may
+/// contain `!/` themselves, so only a separator following an archive name counts.
+pub fn split_member_path(path: &str) -> Option<(&Path, &str)> {
+    path.match_indices(MEMBER_SEPARATOR)
+        .map(|(index, _)| (Path::new(&path[..index]), &path[index + MEMBER_SEPARATOR.len()..]))
+        .find(|(archive, name)| !name.is_empty() && is_archive(archive))
+}
+
+pub fn is_member_path(path: &Path) -> bool {
+    split_member_path(&path.to_string_lossy()).is_some()
+}
+
+pub fn member_path(archive: &Path, name: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}{}", archive.to_string_lossy(), MEMBER_SEPARATOR, name))
+}
+
+/// Paths of the catalogued members of an archive.
+pub fn catalogued_members(archive_path: &Path) -> Vec<String> {
+    catalog::paths_with_prefix(&member_path(archive_path, "").to_string_lossy())
+}
+
+/// `fs::canonicalize` that also accepts member paths, by canonicalizing their archive.
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    match split_member_path(&path.to_string_lossy()) {
+        Some((archive, name)) => Ok(member_path(&fs::canonicalize(archive)?, name)),
+        None => fs::canonicalize(path),
+    }
+}
+
+/// Uncompressed size of a member and modification time (ns) of its archive. Timestamps inside
+/// ZIPs are local time at 2s resolution, so any change to the archive counts as a change.
+pub fn member_metadata(path: &Path) -> io::Result<(u64, u64)> {
+    let path = path.to_string_lossy();
+    let (archive_path, name) = split_member_path(&path).ok_or_else(|| not_a_member(&path))?;
+    let archive = open(archive_path)?;
+    let size = *archive
+        .member_sizes
+        .get(name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} is not in the archive", name)))?;
+    Ok((size, archive.mtime))
+}
+
+/// Image members of an archive with their detected types, in name order. Only formats decoded
+/// in memory are listed; videos, RAW and HEIF/AVIF need a real file for ffmpeg or seeking.
+pub fn list_members(archive_path: &Path) -> io::Result<Vec<Member>> {
+    let cached = open(archive_path)?;
+    let mut archive = cached.zip.clone();
+    let mut members = Vec::new();
+
+    for index in 0..archive.len() {
+        let Ok(mut member) = archive.by_index(index) else {
+            continue;
+        };
+        // `enclosed_name` rejects absolute names and `..`
+        if !member.is_file() || member.size() > MAX_MEMBER_BYTES || member.enclosed_name().is_none() {
+            continue;
+        }
+        let name = member.name().to_string();
+        // Hidden files, including the `__MACOSX` resource forks macOS adds
+        if name.split('/').any(|part| part.starts_with('.') || part == "__MACOSX") {
+            continue;
+        }
+
+        // Only the start of the member is inflated to sniff it
+        let mut header = Vec::with_capacity(media_kind::SNIFF_LEN);
+        let header = (&mut member)
+            .take(media_kind::SNIFF_LEN as u64)
+            .read_to_end(&mut header)
+            .ok()
+            .map(|_| header);
+        let path = member_path(archive_path, &name);
+        if let Some(media) = media_kind::detect_with_header(&path, header.as_deref())
+            .filter(|media| decodes_in_memory(media.kind))
+        {
+            members.push(Member {
+                path,
+                media,
+                size: member.size(),
+                mtime: cached.mtime,
+            });
+        }
+    }
+
+    members.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(members)
+}
+
+fn decodes_in_memory(kind: MediaKind) -> bool {
+    !kind.is_video() && !kind.is_raw() && !matches!(kind, MediaKind::Heic | MediaKind::Avif)
+}
+
+/// Names of every file in an archive, images or not.
+pub fn member_names(archive_path: &Path) -> io::Result<HashSet<String>> {
+    Ok(open(archive_path)?.zip.file_names().map(str::to_string).collect())
+}
+
+/// Reads the bytes of a member such as `comic.cbz!/page_001.jpg`.
+pub fn read_member(path: &Path) -> io::Result<Vec<u8>> {
+    let path = path.to_string_lossy();
+    let (archive_path, name) = split_member_path(&path).ok_or_else(|| not_a_member(&path))?;
+    let mut archive = open(archive_path)?.zip.clone();
+    let mut member = archive.by_name(name).map_err(io::Error::other)?;
+    if member.size() > MAX_MEMBER_BYTES {
+        return Err(io::Error::other(format!("{} is too large to read", name)));
+    }
+
+    let mut data = Vec::with_capacity(member.size() as usize);
+    member.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// The parsed archive at `archive_path`, from the cache unless the archive changed since.
+fn open(archive_path: &Path) -> io::Result<Arc<CachedArchive>> {
+    let metadata = fs::metadata(archive_path)?;
+    let (size, mtime) = (metadata.len(), modified_nanos(&metadata));
+    if let Some(cached) = ARCHIVES.lock().unwrap().get(archive_path) {
+        if (cached.size, cached.mtime) == (size, mtime) {
+            return Ok(Arc::clone(cached));
+        }
+    }
+
+    let file = ArchiveFile {
+        path: Arc::new(archive_path.to_path_buf()),
+        file: None,
+    };
+    let zip = ZipArchive::new(file).map_err(io::Error::other)?;
+    // Read through a clone, which closes its handle when done
+    let mut reader = zip.clone();
+    let member_sizes = (0..reader.len())
+        .filter_map(|index| {
+            let member = reader.by_index(index).ok()?;
+            Some((member.name().to_string(), member.size()))
+        })
+        .collect();
+    let cached = Arc::new(CachedArchive {
+        size,
+        mtime,
+        opened: Instant::now(),
+        zip,
+        member_sizes,
+    });
+
+    let mut archives = ARCHIVES.lock().unwrap();
+    if archives.len() >= MAX_CACHED_ARCHIVES && !archives.contains_key(archive_path) {
+        let oldest = archives
+            .iter()
+            .min_by_key(|(_, archive)| archive.opened)
+            .map(|(path, _)| path.clone());
+        if let Some(oldest) = oldest {
+            archives.remove(&oldest);
+        }
+    }
+    archives.insert(archive_path.to_path_buf(), Arc::clone(&cached));
+    Ok(cached)
+}
+
+fn not_a_member(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not an archive member", path))
+}
+
+/// The indexer only reads plain files, so members are staged for it as their thumbnails, named
+/// by media id so search hits map back to the member (see `member_for_staged`).
+fn staged_path(id: &str) -> PathBuf {
+    EnvPaths::new()
+        .archive_index_path
+        .join(format!("{}.{}", id, thumbnail_profile::current().format.extension()))
+}
+
+/// Whether a member's thumbnail is missing from the archive index directory, or was
+/// regenerated since it was staged.
+fn needs_staging(entry: &CatalogEntry) -> bool {
+    let Some(key) = &entry.thumbnail_key else {
+        return false;
+    };
+    // A regenerated thumbnail is a new file, newer than the staged link or copy
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    match (modified(&staged_path(&entry.id)), modified(&thumbnail_cache::entry_path(key))) {
+        (Some(staged_at), Some(generated_at)) => staged_at < generated_at,
+        _ => true,
+    }
+}
+
+/// Links a member's thumbnail into the archive index directory. Returns the staged path.
+fn stage(entry: &CatalogEntry) -> Option<String> {
+    let thumbnail = thumbnail_cache::entry_path(entry.thumbnail_key.as_ref()?);
+    let staged = staged_path(&entry.id);
+
+    let _ = fs::remove_file(&staged);
+    fs::hard_link(&thumbnail, &staged)
+        .or_else(|_| fs::copy(&thumbnail, &staged).map(|_| ()))
+        .ok()?;
+    Some(staged.to_string_lossy().into_owned())
+}
+
+/// Removes staged files whose member left the catalog, or that were staged in another thumbnail
+/// format. Returns their paths.
+fn unstage_orphans() -> Vec<String> {
+    let Ok(staged_files) = fs::read_dir(EnvPaths::new().archive_index_path) else {
+        return Vec::new();
+    };
+    let extension = thumbnail_profile::current().format.extension();
+
+    staged_files
+        .flatten()
+        .map(|staged| staged.path())
+        .filter(|staged| {
+            staged.extension().is_none_or(|staged_extension| staged_extension != extension)
+                || staged
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .is_none_or(|id| catalog::get_by_id(id).is_none())
+        })
+        .filter(|staged| fs::remove_file(staged).is_ok())
+        .map(|staged| staged.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Stages the given members for the indexer as an `export` job, drops members that are gone,
+/// and tells the indexer about both. Only members that need (re)staging make a job.
+pub fn sync_indexer<'a>(app: &AppHandle, member_paths: impl Iterator<Item = &'a str>) {
+    let pending: Vec<CatalogEntry> = member_paths
+        .filter_map(catalog::get)
+        .filter(needs_staging)
+        .collect();
+    let mut staged = Vec::new();
+    if !pending.is_empty() {
+        let job = jobs::create(app, "export", format!("Staging {} archive images for indexing", pending.len()));
+        if job.run() {
+            for entry in &pending {
+                if !job.checkpoint() {
+                    break;
+                }
+                staged.extend(stage(entry));
+            }
+            job.complete();
+        }
+    }
+    let orphans = unstage_orphans();
+
+    if !orphans.is_empty() {
+        send_command_to_python(&json!({ "unindex_files": orphans }).to_string());
+    }
+    if !staged.is_empty() {
+        send_command_to_python(&json!({ "index_files": staged }).to_string());
+    }
+}
+
+/// Maps a staged file reported by the indexer back to its member path.
+pub fn member_for_staged(path: &Path) -> Option<String> {
+    if path.parent()? != EnvPaths::new().archive_index_path {
+        return None;
+    }
+    catalog::get_by_id(path.file_stem()?.to_str()?).map(|entry| entry.path)
+}
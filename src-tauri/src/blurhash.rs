@@ -0,0 +1,113 @@
+use image::DynamicImage;
+use std::f32::consts::PI;
+
+const BASE83: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+// The hash only holds a few cosine components, so a tiny copy of the image is plenty
+const SAMPLE_EDGE: u32 = 32;
+// Components along the long and short edge; 4x3 hashes are ~28 characters
+const LONG_COMPONENTS: u32 = 4;
+const SHORT_COMPONENTS: u32 = 3;
+
+/// BlurHash (https://blurha.sh) of `img`: a short string the frontend decodes into a blurred
+/// preview to paint before the thumbnail loads. Alpha is ignored.
+pub fn encode(img: &DynamicImage) -> String {
+    let sample = img.thumbnail(SAMPLE_EDGE, SAMPLE_EDGE).to_rgb8();
+    let (width, height) = sample.dimensions();
+    let (components_x, components_y) = if width >= height {
+        (LONG_COMPONENTS, SHORT_COMPONENTS)
+    } else {
+        (SHORT_COMPONENTS, LONG_COMPONENTS)
+    };
+
+    let linear: Vec<[f32; 3]> = sample
+        .pixels()
+        .map(|pixel| pixel.0.map(srgb_to_linear))
+        .collect();
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0f32; 3];
+            for y in 0..height {
+                for x in 0..width {
+                    let basis = (PI * i as f32 * x as f32 / width as f32).cos()
+                        * (PI * j as f32 * y as f32 / height as f32).cos();
+                    let pixel = linear[(y * width + x) as usize];
+                    for (sum, value) in factor.iter_mut().zip(pixel) {
+                        *sum += basis * value;
+                    }
+                }
+            }
+            let scale = normalisation / (width * height) as f32;
+            factors.push(factor.map(|value| value * scale));
+        }
+    }
+
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let mut hash = String::with_capacity(4 + 2 * factors.len());
+    push_base83(&mut hash, (components_x - 1) + (components_y - 1) * 9, 1);
+
+    let maximum = if ac.is_empty() {
+        push_base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let actual_maximum = ac.iter().flatten().fold(0.0f32, |maximum, value| maximum.max(value.abs()));
+        let quantised = ((actual_maximum * 166.0 - 0.5).floor()).clamp(0.0, 82.0) as u32;
+        push_base83(&mut hash, quantised, 1);
+        (quantised + 1) as f32 / 166.0
+    };
+
+    let [r, g, b] = dc.map(linear_to_srgb);
+    push_base83(&mut hash, (r << 16) + (g << 8) + b, 4);
+    for component in ac {
+        let [r, g, b] = component.map(|value| {
+            let scaled = value / maximum;
+            (scaled.signum() * scaled.abs().sqrt() * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32
+        });
+        push_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    hash
+}
+
+fn push_base83(hash: &mut String, value: u32, length: u32) {
+    for digit in (0..length).rev() {
+        hash.push(BASE83[(value / 83u32.pow(digit) % 83) as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u32 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.003_130_8 {
+        (value * 12.92 * 255.0 + 0.5) as u32
+    } else {
+        ((1.055 * value.powf(1.0 / 2.4) - 0.055) * 255.0 + 0.5) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    // Reference hashes from the woltapp/blurhash encoder with the same component counts
+    #[test]
+    fn matches_reference_landscape() {
+        let img = RgbImage::from_fn(32, 24, |x, y| Rgb([(x * 8) as u8, (y * 10) as u8, 128]));
+        assert_eq!(encode(&DynamicImage::ImageRgb8(img)), "LxH27k2swxX8mHWWjtf7gJfjfQfj");
+    }
+
+    #[test]
+    fn matches_reference_portrait() {
+        let img = RgbImage::from_fn(20, 32, |x, y| Rgb([(255 - x * 12) as u8, (x * y % 256) as u8, (y * 8) as u8]));
+        assert_eq!(encode(&DynamicImage::ImageRgb8(img)), "T?IgB3{l#Cm%n$kTebg1f%niowi}");
+    }
+}
@@ -0,0 +1,597 @@
+use crate::image_processor::placeholder_thumbnail;
+use crate::initialise::EnvPaths;
+use crate::thumbnail_cache;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Row};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+lazy_static::lazy_static! {
+    // Opened on first use by `catalog`; `None` until then, or while it fails to open
+    static ref CATALOG: Mutex<Option<Connection>> = Mutex::new(None);
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS media (
+        path TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        mtime INTEGER NOT NULL,
+        media_type TEXT NOT NULL,
+        width INTEGER,
+        height INTEGER,
+        thumbnail_key TEXT,
+        thumbnail_error TEXT
+    );
+";
+
+// Columns added after the first release; `open_catalog` adds whichever an older db lacks
+const MIGRATIONS: &[(&str, &str)] = &[
+    ("id", "TEXT"),
+    ("dev", "INTEGER"),
+    ("inode", "INTEGER"),
+    ("name", "TEXT"),
+    ("mime_type", "TEXT"),
+    ("extension_mismatch", "INTEGER NOT NULL DEFAULT 0"),
+    ("orientation", "INTEGER"),
+    ("frame_count", "INTEGER"),
+    ("duration_ms", "INTEGER"),
+    ("placeholder", "TEXT"),
+    ("phash", "INTEGER"),
+    ("content_hash", "TEXT"),
+    ("thumbnail_version", "TEXT"),
+];
+
+// Part of `thumbnail_version`; bump when thumbnailing fills in rows differently, so older rows
+// are thumbnailed again (mostly cache hits) instead of checking each column, e.g. 2 dropped
+// perceptual hashes taken from the thumbnail rather than the source
+const ROW_VERSION: u32 = 2;
+
+const INDEXES: &str = "
+    CREATE INDEX IF NOT EXISTS media_id ON media(id);
+    CREATE INDEX IF NOT EXISTS media_identity ON media(dev, inode);
+    CREATE INDEX IF NOT EXISTS media_name ON media(name COLLATE NOCASE);
+    CREATE INDEX IF NOT EXISTS media_mtime ON media(mtime);
+    CREATE INDEX IF NOT EXISTS media_size ON media(size);
+    CREATE INDEX IF NOT EXISTS media_type ON media(media_type);
+    CREATE INDEX IF NOT EXISTS media_pixels ON media((width * height));
+    CREATE INDEX IF NOT EXISTS media_thumbnail ON media(thumbnail_key);
+";
+
+/// Sort keys accepted by `query_media`.
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    #[default]
+    Name,
+    Mtime,
+    Size,
+    Type,
+    Dimensions,
+}
+
+impl SortKey {
+    fn order_by(self) -> &'static str {
+        match self {
+            SortKey::Name => "name COLLATE NOCASE",
+            SortKey::Mtime => "mtime",
+            SortKey::Size => "size",
+            SortKey::Type => "media_type",
+            SortKey::Dimensions => "(width * height)",
+        }
+    }
+}
+
+/// Optional filters for `query_media`; all given filters must match.
+#[derive(Deserialize, Default)]
+pub struct MediaFilters {
+    pub media_types: Option<Vec<String>>,
+    /// Only files below this directory
+    pub path_prefix: Option<String>,
+    /// Case-insensitive substring of the file name
+    pub name_contains: Option<String>,
+}
+
+// Largest page `query_media` returns; bigger requests would rebuild one huge IPC payload
+const MAX_PAGE_SIZE: usize = 1000;
+
+const ENTRY_COLUMNS: &str =
+    "path, size, mtime, media_type, width, height, thumbnail_key, thumbnail_error, id, dev, inode, mime_type, extension_mismatch, orientation, frame_count, duration_ms, placeholder, phash, content_hash, thumbnail_version";
+
+/// Everything the scanner knows about a discovered file.
+#[derive(Clone, Debug)]
+pub struct CatalogEntry {
+    pub id: String,
+    pub path: String,
+    pub size: u64,
+    pub mtime: u64,
+    /// `image` or `video`
+    pub media_type: String,
+    /// `None` for rows written before content sniffing
+    pub mime_type: Option<String>,
+    pub extension_mismatch: bool,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// EXIF orientation (1-8); `width`/`height` are already as displayed
+    pub orientation: Option<u32>,
+    /// 1 for stills, more for animated GIFs and WebPs; `None` until thumbnailed
+    pub frame_count: Option<u32>,
+    pub duration_ms: Option<u64>,
+    /// BlurHash of the thumbnail, painted by the UI until the thumbnail file loads
+    pub placeholder: Option<String>,
+    /// dHash of the decoded source (not the thumbnail, so profiles don't change it), for finding
+    /// near duplicates; `None` until `duplicates::find_duplicates` computes it for cache hits
+    pub phash: Option<u64>,
+    /// blake3 of the file, computed by `duplicates::find_duplicates` for files that share a size
+    pub content_hash: Option<String>,
+    pub thumbnail_key: Option<String>,
+    pub thumbnail_error: Option<String>,
+    /// `thumbnail_version()` when the thumbnail was made, or failed to be
+    pub thumbnail_version: Option<String>,
+    // Device/inode pair used to recognise a file that moved since the last scan
+    pub dev: Option<u64>,
+    pub inode: Option<u64>,
+}
+
+impl CatalogEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            path: row.get(0)?,
+            size: row.get::<_, i64>(1)? as u64,
+            mtime: row.get::<_, i64>(2)? as u64,
+            media_type: row.get(3)?,
+            width: row.get(4)?,
+            height: row.get(5)?,
+            thumbnail_key: row.get(6)?,
+            thumbnail_error: row.get(7)?,
+            id: row.get::<_, Option<String>>(8)?.unwrap_or_default(),
+            dev: row.get::<_, Option<i64>>(9)?.map(|dev| dev as u64),
+            inode: row.get::<_, Option<i64>>(10)?.map(|inode| inode as u64),
+            mime_type: row.get(11)?,
+            extension_mismatch: row.get(12)?,
+            orientation: row.get(13)?,
+            frame_count: row.get(14)?,
+            duration_ms: row.get::<_, Option<i64>>(15)?.map(|duration| duration as u64),
+            placeholder: row.get(16)?,
+            phash: row.get::<_, Option<i64>>(17)?.map(|phash| phash as u64),
+            content_hash: row.get(18)?,
+            thumbnail_version: row.get(19)?,
+        })
+    }
+
+    /// Whether the entry still describes `size`/`mtime` and was thumbnailed by the current row
+    /// version, pipeline and profile, with its thumbnail still on disk. Files that failed to
+    /// thumbnail stay failed until they or the profile change.
+    pub fn is_current(&self, size: u64, mtime: u64) -> bool {
+        if self.size != size || self.mtime != mtime || self.mime_type.is_none() {
+            return false;
+        }
+        if self.thumbnail_version.as_deref() != Some(thumbnail_version().as_str()) {
+            return false;
+        }
+
+        match &self.thumbnail_key {
+            Some(key) => {
+                thumbnail_cache::entry_path(key).exists()
+                    && (!self.is_animated() || thumbnail_cache::preview_path(key).exists())
+            }
+            None => self.thumbnail_error.is_some(),
+        }
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.frame_count.is_some_and(|frame_count| frame_count > 1)
+    }
+
+    pub fn to_media_item(&self) -> Value {
+        let thumbnail = match &self.thumbnail_key {
+            Some(key) => thumbnail_cache::entry_path(key).to_string_lossy().into_owned(),
+            None => placeholder_thumbnail(self.mime_type.as_deref()),
+        };
+
+        let mut item = json!({
+            "id": self.id,
+            "file_path": self.path,
+            "path": thumbnail,
+            "type": self.media_type,
+            "mime_type": self.mime_type,
+            "extension_mismatch": self.extension_mismatch,
+            "width": self.width,
+            "height": self.height,
+            "orientation": self.orientation,
+            "animated": self.is_animated(),
+            "placeholder": self.placeholder,
+            // Queued; a `thumbnail_ready` event carries the item once it has one
+            "thumbnail_pending": self.thumbnail_key.is_none() && self.thumbnail_error.is_none()
+        });
+        if let Some(error) = &self.thumbnail_error {
+            item["thumbnail_error"] = json!(error);
+        }
+        if let (true, Some(key)) = (self.is_animated(), &self.thumbnail_key) {
+            item["frame_count"] = json!(self.frame_count);
+            item["duration_ms"] = json!(self.duration_ms);
+            item["preview"] = json!(thumbnail_cache::preview_path(key));
+        }
+        item
+    }
+}
+
+fn open_catalog() -> rusqlite::Result<Connection> {
+    let paths = EnvPaths::new();
+    let connection = Connection::open(&paths.catalog_path)?;
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.execute_batch(SCHEMA)?;
+
+    let existing: HashSet<String> = connection
+        .prepare("PRAGMA table_info(media)")?
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<_>>()?;
+    for (column, declaration) in MIGRATIONS {
+        if !existing.contains(*column) {
+            connection.execute_batch(&format!("ALTER TABLE media ADD COLUMN {} {};", column, declaration))?;
+        }
+    }
+    if !existing.contains("name") {
+        backfill_names(&connection)?;
+    }
+    connection.execute_batch(INDEXES)?;
+    Ok(connection)
+}
+
+/// The locked catalog connection.
+struct CatalogGuard(MutexGuard<'static, Option<Connection>>);
+
+impl Deref for CatalogGuard {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.0.as_ref().expect("opened by `catalog`")
+    }
+}
+
+impl DerefMut for CatalogGuard {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.0.as_mut().expect("opened by `catalog`")
+    }
+}
+
+/// Locks the catalog, opening it on first use. A db that can't be opened (locked, corrupt) is
+/// reported to the caller and tried again on the next call.
+fn catalog() -> Result<CatalogGuard, String> {
+    let mut catalog = CATALOG.lock().unwrap();
+    if catalog.is_none() {
+        *catalog = Some(open_catalog().map_err(|e| format!("Failed to open media catalog: {}", e))?);
+    }
+    Ok(CatalogGuard(catalog))
+}
+
+fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn backfill_names(connection: &Connection) -> rusqlite::Result<()> {
+    let paths: Vec<String> = connection
+        .prepare("SELECT path FROM media")?
+        .query_map([], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    for path in paths {
+        connection.execute("UPDATE media SET name = ?1 WHERE path = ?2", params![file_name(&path), path])?;
+    }
+    Ok(())
+}
+
+pub fn get(path: &str) -> Option<CatalogEntry> {
+    let connection = catalog().ok()?;
+    connection
+        .query_row(
+            &format!("SELECT {} FROM media WHERE path = ?1", ENTRY_COLUMNS),
+            params![path],
+            CatalogEntry::from_row,
+        )
+        .optional()
+        .unwrap_or(None)
+}
+
+pub fn get_by_id(id: &str) -> Option<CatalogEntry> {
+    let connection = catalog().ok()?;
+    connection
+        .query_row(
+            &format!("SELECT {} FROM media WHERE id = ?1", ENTRY_COLUMNS),
+            params![id],
+            CatalogEntry::from_row,
+        )
+        .optional()
+        .unwrap_or(None)
+}
+
+/// Finds an entry for the same device/inode whose path no longer exists, i.e. the file was
+/// moved or renamed, and removes it so the caller can carry its id over to the new path.
+pub fn take_moved(dev: u64, inode: u64) -> Option<CatalogEntry> {
+    let connection = catalog().ok()?;
+    let candidates: Vec<CatalogEntry> = connection
+        .prepare(&format!("SELECT {} FROM media WHERE dev = ?1 AND inode = ?2", ENTRY_COLUMNS))
+        .and_then(|mut statement| {
+            statement
+                .query_map(params![dev as i64, inode as i64], CatalogEntry::from_row)?
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let moved = candidates
+        .into_iter()
+        .find(|entry| !Path::new(&entry.path).exists())?;
+    connection
+        .execute("DELETE FROM media WHERE path = ?1", params![moved.path])
+        .ok()?;
+    Some(moved)
+}
+
+pub fn upsert(entry: &CatalogEntry) -> Result<(), String> {
+    let connection = catalog()?;
+    connection
+        .execute(
+            "INSERT INTO media (path, size, mtime, media_type, width, height, thumbnail_key, thumbnail_error, id, dev, inode, name, mime_type, extension_mismatch, orientation, frame_count, duration_ms, placeholder, phash, content_hash, thumbnail_version)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21)
+             ON CONFLICT(path) DO UPDATE SET
+                size = excluded.size,
+                mtime = excluded.mtime,
+                media_type = excluded.media_type,
+                width = excluded.width,
+                height = excluded.height,
+                thumbnail_key = excluded.thumbnail_key,
+                thumbnail_error = excluded.thumbnail_error,
+                id = excluded.id,
+                dev = excluded.dev,
+                inode = excluded.inode,
+                name = excluded.name,
+                mime_type = excluded.mime_type,
+                extension_mismatch = excluded.extension_mismatch,
+                orientation = excluded.orientation,
+                frame_count = excluded.frame_count,
+                duration_ms = excluded.duration_ms,
+                placeholder = excluded.placeholder,
+                phash = excluded.phash,
+                content_hash = excluded.content_hash,
+                thumbnail_version = excluded.thumbnail_version",
+            params![
+                entry.path,
+                entry.size as i64,
+                entry.mtime as i64,
+                entry.media_type,
+                entry.width,
+                entry.height,
+                entry.thumbnail_key,
+                entry.thumbnail_error,
+                entry.id,
+                entry.dev.map(|dev| dev as i64),
+                entry.inode.map(|inode| inode as i64),
+                file_name(&entry.path),
+                entry.mime_type,
+                entry.extension_mismatch,
+                entry.orientation,
+                entry.frame_count,
+                entry.duration_ms.map(|duration| duration as i64),
+                entry.placeholder,
+                entry.phash.map(|phash| phash as i64),
+                entry.content_hash,
+                entry.thumbnail_version
+            ],
+        )
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Stores the perceptual hash computed for an entry, unless the file was re-catalogued with
+/// another size/mtime meanwhile.
+pub fn set_phash(entry: &CatalogEntry, phash: u64) -> Result<(), String> {
+    let connection = catalog()?;
+    connection
+        .execute(
+            "UPDATE media SET phash = ?1 WHERE path = ?2 AND size = ?3 AND mtime = ?4",
+            params![phash as i64, entry.path, entry.size as i64, entry.mtime as i64],
+        )
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Stores the content hash of an entry, unless the file was re-catalogued with another
+/// size/mtime meanwhile.
+pub fn set_content_hash(entry: &CatalogEntry, content_hash: &str) -> Result<(), String> {
+    let connection = catalog()?;
+    connection
+        .execute(
+            "UPDATE media SET content_hash = ?1 WHERE path = ?2 AND size = ?3 AND mtime = ?4",
+            params![content_hash, entry.path, entry.size as i64, entry.mtime as i64],
+        )
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub fn all_entries() -> Result<Vec<CatalogEntry>, String> {
+    let connection = catalog()?;
+    let mut statement = connection
+        .prepare(&format!("SELECT {} FROM media ORDER BY path", ENTRY_COLUMNS))
+        .map_err(|e| e.to_string())?;
+
+    let entries = statement
+        .query_map([], CatalogEntry::from_row)
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(entries)
+}
+
+/// Drops every entry that was not seen by a completed scan and returns the removed paths.
+pub fn remove_missing(seen: &HashSet<String>) -> Result<Vec<String>, String> {
+    let removed: Vec<String> = all_entries()?
+        .into_iter()
+        .map(|entry| entry.path)
+        .filter(|path| !seen.contains(path))
+        .collect();
+
+    remove(&removed)?;
+    Ok(removed)
+}
+
+pub fn remove(paths: &[String]) -> Result<(), String> {
+    let mut connection = catalog()?;
+    let transaction = connection.transaction().map_err(|e| e.to_string())?;
+    for path in paths {
+        transaction
+            .execute("DELETE FROM media WHERE path = ?1", params![path])
+            .map_err(|e| e.to_string())?;
+    }
+    transaction.commit().map_err(|e| e.to_string())
+}
+
+/// What `thumbnail_entry` stamps on rows: the row version with the thumbnail pipeline and profile.
+pub fn thumbnail_version() -> String {
+    format!("{}.{}", ROW_VERSION, thumbnail_cache::pipeline_version())
+}
+
+/// Marks the entries whose thumbnails were evicted from the cache as pending again.
+pub fn clear_thumbnail_keys(keys: &[String]) -> Result<(), String> {
+    let mut connection = catalog()?;
+    let transaction = connection.transaction().map_err(|e| e.to_string())?;
+    for key in keys {
+        transaction
+            .execute("UPDATE media SET thumbnail_key = NULL WHERE thumbnail_key = ?1", params![key])
+            .map_err(|e| e.to_string())?;
+    }
+    transaction.commit().map_err(|e| e.to_string())
+}
+
+pub fn clear() -> Result<(), String> {
+    let connection = catalog()?;
+    connection
+        .execute("DELETE FROM media", [])
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+pub fn count() -> Result<usize, String> {
+    let connection = catalog()?;
+    connection
+        .query_row("SELECT COUNT(*) FROM media", [], |row| row.get::<_, i64>(0))
+        .map(|count| count as usize)
+        .map_err(|e| e.to_string())
+}
+
+/// Paths starting with `prefix`, e.g. the members of an archive.
+pub fn paths_with_prefix(prefix: &str) -> Vec<String> {
+    let Ok(connection) = catalog() else {
+        return Vec::new();
+    };
+    connection
+        .prepare("SELECT path FROM media WHERE substr(path, 1, length(?1)) = ?1 ORDER BY path")
+        .and_then(|mut statement| statement.query_map(params![prefix], |row| row.get(0))?.collect())
+        .unwrap_or_default()
+}
+
+/// One page of entries matching `filters` in `sort` order, plus the total number of matches.
+pub fn query(
+    offset: usize,
+    limit: usize,
+    sort: SortKey,
+    descending: bool,
+    filters: &MediaFilters,
+) -> Result<(Vec<CatalogEntry>, usize), String> {
+    let mut conditions = Vec::new();
+    let mut values: Vec<SqlValue> = Vec::new();
+
+    if let Some(media_types) = filters.media_types.as_ref().filter(|types| !types.is_empty()) {
+        let placeholders = vec!["?"; media_types.len()].join(", ");
+        conditions.push(format!("media_type IN ({})", placeholders));
+        values.extend(media_types.iter().cloned().map(SqlValue::Text));
+    }
+    if let Some(prefix) = &filters.path_prefix {
+        // A plain prefix comparison; LIKE would treat `_` and `%` in paths as wildcards
+        let prefix = format!("{}/", prefix.trim_end_matches('/'));
+        conditions.push("substr(path, 1, length(?)) = ?".to_string());
+        values.push(SqlValue::Text(prefix.clone()));
+        values.push(SqlValue::Text(prefix));
+    }
+    if let Some(needle) = filters.name_contains.as_ref().filter(|needle| !needle.is_empty()) {
+        conditions.push("instr(lower(name), lower(?)) > 0".to_string());
+        values.push(SqlValue::Text(needle.clone()));
+    }
+
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+    let direction = if descending { "DESC" } else { "ASC" };
+
+    let connection = catalog()?;
+    let total = connection
+        .query_row(
+            &format!("SELECT COUNT(*) FROM media {}", where_clause),
+            params_from_iter(values.iter()),
+            |row| row.get::<_, i64>(0),
+        )
+        .map_err(|e| e.to_string())? as usize;
+
+    // Path as tie-breaker keeps pages stable when many rows share a sort value
+    let sql = format!(
+        "SELECT {} FROM media {} ORDER BY {} {}, path {} LIMIT ? OFFSET ?",
+        ENTRY_COLUMNS,
+        where_clause,
+        sort.order_by(),
+        direction,
+        direction
+    );
+    values.push(SqlValue::Integer(limit as i64));
+    values.push(SqlValue::Integer(offset as i64));
+
+    let mut statement = connection.prepare(&sql).map_err(|e| e.to_string())?;
+    let entries = statement
+        .query_map(params_from_iter(values.iter()), CatalogEntry::from_row)
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .collect();
+
+    Ok((entries, total))
+}
+
+/// Paged, sorted listing served from the catalog, so the grid can virtualize large libraries.
+/// Pages hold at most `MAX_PAGE_SIZE` items; the `limit` applied is returned.
+#[tauri::command]
+pub async fn query_media(
+    offset: usize,
+    limit: usize,
+    sort: Option<SortKey>,
+    descending: Option<bool>,
+    filters: Option<MediaFilters>,
+) -> Result<Value, String> {
+    let limit = limit.min(MAX_PAGE_SIZE);
+    let (entries, total) = query(
+        offset,
+        limit,
+        sort.unwrap_or_default(),
+        descending.unwrap_or(false),
+        &filters.unwrap_or_default(),
+    )?;
+
+    thumbnail_cache::touch(entries.iter().filter_map(|entry| entry.thumbnail_key.as_deref()));
+    Ok(json!({
+        "items": entries.iter().map(CatalogEntry::to_media_item).collect::<Vec<_>>(),
+        "total": total,
+        "offset": offset,
+        "limit": limit
+    }))
+}
+
+/// Media items for everything catalogued so far, so the grid can fill before any disk walk.
+#[tauri::command]
+pub async fn load_catalog() -> Result<Vec<Value>, String> {
+    let entries = all_entries()?;
+    thumbnail_cache::touch(entries.iter().filter_map(|entry| entry.thumbnail_key.as_deref()));
+    Ok(entries.iter().map(CatalogEntry::to_media_item).collect())
+}
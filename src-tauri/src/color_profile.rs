@@ -0,0 +1,102 @@
+use crate::exif::{self, TiffReader};
+use flate2::read::ZlibDecoder;
+use image::DynamicImage;
+use qcms::{DataType, Intent, Profile, Transform};
+use std::io::{Cursor, Read};
+
+// Real profiles are a few KB; larger claims are corrupt
+const MAX_PROFILE_BYTES: usize = 4 * 1024 * 1024;
+// APP2 payload prefix; the sequence number and segment count follow
+const JPEG_ICC_MARKER: &[u8] = b"ICC_PROFILE\0";
+
+lazy_static::lazy_static! {
+    static ref SRGB: Box<Profile> = {
+        let mut srgb = Profile::new_sRGB();
+        srgb.precache_output_transform();
+        srgb
+    };
+}
+
+/// ICC profile embedded in an in-memory JPEG, PNG, WebP or TIFF.
+pub fn embedded_profile(data: &[u8]) -> Option<Vec<u8>> {
+    match data {
+        [0xFF, 0xD8, ..] => jpeg_profile(data),
+        [0x89, b'P', b'N', b'G', ..] => png_profile(data),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => exif::riff_chunks(data)
+            .find(|(id, _)| id == b"ICCP")
+            .map(|(_, profile)| profile.to_vec()),
+        [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => tiff_profile(data),
+        _ => None,
+    }
+}
+
+/// Profiles larger than a segment are split across numbered APP2 segments.
+fn jpeg_profile(jpeg: &[u8]) -> Option<Vec<u8>> {
+    let mut parts: Vec<(u8, &[u8])> = exif::jpeg_segments(jpeg)
+        .filter(|(marker, _, payload)| *marker == 0xE2 && payload.starts_with(JPEG_ICC_MARKER))
+        .filter_map(|(_, _, payload)| {
+            let sequence = *payload.get(JPEG_ICC_MARKER.len())?;
+            Some((sequence, payload.get(JPEG_ICC_MARKER.len() + 2..)?))
+        })
+        .collect();
+    if parts.is_empty() {
+        return None;
+    }
+
+    parts.sort_by_key(|(sequence, _)| *sequence);
+    Some(parts.into_iter().flat_map(|(_, part)| part.iter().copied()).collect())
+}
+
+/// `iCCP`: profile name, NUL, compression method, then the zlib-compressed profile.
+fn png_profile(png: &[u8]) -> Option<Vec<u8>> {
+    let (_, chunk) = exif::png_chunks(png).find(|(kind, _)| kind == b"iCCP")?;
+    let name_end = chunk.iter().position(|&byte| byte == 0)?;
+    let mut profile = Vec::new();
+    ZlibDecoder::new(chunk.get(name_end + 2..)?)
+        .take(MAX_PROFILE_BYTES as u64)
+        .read_to_end(&mut profile)
+        .ok()?;
+    Some(profile)
+}
+
+fn tiff_profile(tiff: &[u8]) -> Option<Vec<u8>> {
+    let mut tiff = TiffReader::new(Cursor::new(tiff), 0)?;
+    let offset = tiff.first_ifd()?;
+    let (entries, _) = tiff.read_ifd(offset)?;
+    let entry = entries.iter().find(|entry| entry.tag == exif::TAG_ICC_PROFILE)?;
+    if entry.count as usize > MAX_PROFILE_BYTES {
+        return None;
+    }
+
+    let mut profile = vec![0u8; entry.count as usize];
+    tiff.read_at(tiff.value_offset(entry), &mut profile)?;
+    Some(profile)
+}
+
+/// Converts `img` from its embedded `icc` profile to sRGB, so wide-gamut (Display P3, Adobe RGB)
+/// sources don't look washed out. Unusable, non-RGB and sRGB profiles leave `img` as it is.
+pub fn to_srgb(img: DynamicImage, icc: &[u8]) -> DynamicImage {
+    let Some(profile) = Profile::new_from_slice(icc, false) else {
+        return img;
+    };
+    if profile.is_sRGB() {
+        return img;
+    }
+
+    // Thumbnails are 8-bit either way, so 16-bit and float sources are converted down first
+    if img.color().has_alpha() {
+        let Some(transform) = Transform::new(&profile, &SRGB, DataType::RGBA8, Intent::Perceptual) else {
+            return img;
+        };
+        let mut rgba = img.to_rgba8();
+        transform.apply(&mut rgba);
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        let Some(transform) = Transform::new(&profile, &SRGB, DataType::RGB8, Intent::Perceptual) else {
+            return img;
+        };
+        let mut rgb = img.to_rgb8();
+        transform.apply(&mut rgb);
+        DynamicImage::ImageRgb8(rgb)
+    }
+}
@@ -0,0 +1,457 @@
+use crate::archive;
+use crate::catalog::{self, CatalogEntry};
+use crate::image_processor;
+use crate::jobs::{self, Job};
+use crate::media_kind::MediaKind;
+use crate::utils::modified_nanos;
+use image::imageops::FilterType;
+use image::DynamicImage;
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use tauri::AppHandle;
+
+const CONFIG_KEY: &str = "duplicates";
+// Out of 64 bits; resized and re-encoded copies usually differ in a handful
+const DEFAULT_MAX_DISTANCE: u32 = 10;
+// Sources are first shrunk to fit this square, so the hash doesn't depend on how large (or how
+// DCT-scaled) the decode was
+const DHASH_SAMPLE_EDGE: u32 = 64;
+// Files hashed or decoded per parallel batch; pauses and cancellation take effect between them
+const BATCH_SIZE: usize = 256;
+
+/// Difference hash of a decoded, upright source: one bit per pixel of a 9x8 grayscale downscale
+/// of the whole image, set where the pixel is darker than its right neighbour. Survives
+/// resizing, re-encoding and small colour shifts.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img
+        .thumbnail(DHASH_SAMPLE_EDGE, DHASH_SAMPLE_EDGE)
+        .resize_exact(9, 8, FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let darker = small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0];
+            hash = hash << 1 | darker as u64;
+        }
+    }
+    hash
+}
+
+fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// The near-duplicate threshold from the config's `duplicates.max_distance`.
+pub fn max_distance(config: &Value) -> u32 {
+    config[CONFIG_KEY]["max_distance"]
+        .as_u64()
+        .map_or(DEFAULT_MAX_DISTANCE, |distance| distance.min(64) as u32)
+}
+
+fn content_hash(path: &str) -> io::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    if archive::is_member_path(Path::new(path)) {
+        hasher.update(&archive::read_member(Path::new(path))?);
+    } else {
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Size and mtime of a catalogued file as the scan records them, without the canonicalization
+/// of a `SourceFingerprint`. Archive members come from the cached archive listing.
+fn current_metadata(path: &str) -> Option<(u64, u64)> {
+    let path = Path::new(path);
+    if archive::is_member_path(path) {
+        return archive::member_metadata(path).ok();
+    }
+    fs::metadata(path)
+        .ok()
+        .map(|metadata| (metadata.len(), modified_nanos(&metadata)))
+}
+
+/// Runs `f` over `items` in parallel batches. Pauses are waited out between batches on the
+/// calling thread, never inside the rayon pool. Returns false once the job is cancelled.
+fn par_batches<T: Send>(job: &Job, items: &mut [T], f: impl Fn(&mut T) + Sync) -> bool {
+    for batch in items.chunks_mut(BATCH_SIZE) {
+        if !job.checkpoint() {
+            return false;
+        }
+        batch.par_iter_mut().for_each(&f);
+    }
+    job.checkpoint()
+}
+
+/// Metric tree over perceptual hashes, so finding the hashes within a distance of one doesn't
+/// compare it against every other.
+#[derive(Default)]
+struct BkTree {
+    // Hash, entry index and children by their distance to this node
+    nodes: Vec<(u64, usize, HashMap<u32, usize>)>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, index: usize) {
+        let new_node = self.nodes.len();
+        let mut node = 0;
+        while node < new_node {
+            let edge = distance(self.nodes[node].0, hash);
+            match self.nodes[node].2.get(&edge) {
+                Some(&child) => node = child,
+                None => {
+                    self.nodes[node].2.insert(edge, new_node);
+                    break;
+                }
+            }
+        }
+        self.nodes.push((hash, index, HashMap::new()));
+    }
+
+    /// Entry indices of the hashes within `max_distance` of `hash`.
+    fn within(&self, hash: u64, max_distance: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() { vec![] } else { vec![0] };
+        while let Some(node) = pending.pop() {
+            let (node_hash, index, children) = &self.nodes[node];
+            let node_distance = distance(*node_hash, hash);
+            if node_distance <= max_distance {
+                found.push(*index);
+            }
+            // The triangle inequality rules out subtrees outside this band
+            let band = node_distance.saturating_sub(max_distance)..=node_distance + max_distance;
+            pending.extend(
+                children
+                    .iter()
+                    .filter(|(edge, _)| band.contains(edge))
+                    .map(|(_, &child)| child),
+            );
+        }
+        found
+    }
+}
+
+/// Ranks copies for keeping: the most pixels, then files outside archives, the largest file
+/// (the least compressed), no extension mismatch, the oldest and the shortest path.
+fn keeper_rank(entry: &CatalogEntry) -> impl Ord {
+    (
+        entry.width.unwrap_or(0) as u64 * entry.height.unwrap_or(0) as u64,
+        !archive::is_member_path(Path::new(&entry.path)),
+        entry.size,
+        !entry.extension_mismatch,
+        Reverse(entry.mtime),
+        Reverse(entry.path.len()),
+    )
+}
+
+fn keeper(entries: &[CatalogEntry], group: &[usize]) -> usize {
+    group
+        .iter()
+        .copied()
+        .max_by_key(|&index| keeper_rank(&entries[index]))
+        .expect("groups have members")
+}
+
+/// Groups of entries (as indices into `entries`) with identical contents. Only files that share
+/// their size with another are hashed; hashes are kept in the catalog until the file changes.
+/// `None` if the job was cancelled.
+fn exact_groups(job: &Job, entries: &mut [CatalogEntry]) -> Option<Vec<Vec<usize>>> {
+    let mut size_counts: HashMap<u64, usize> = HashMap::new();
+    for entry in entries.iter() {
+        *size_counts.entry(entry.size).or_default() += 1;
+    }
+
+    let mut unhashed: Vec<&mut CatalogEntry> = entries
+        .iter_mut()
+        .filter(|entry| entry.content_hash.is_none() && size_counts[&entry.size] > 1)
+        .collect();
+    let hashed = par_batches(job, &mut unhashed, |entry| match content_hash(&entry.path) {
+        Ok(hash) => {
+            if let Err(e) = catalog::set_content_hash(entry, &hash) {
+                println!("Failed to store content hash of {}: {}", entry.path, e);
+            }
+            entry.content_hash = Some(hash);
+        }
+        Err(e) => println!("Failed to hash {}: {}", entry.path, e),
+    });
+    if !hashed {
+        return None;
+    }
+
+    let mut by_hash: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, entry) in entries.iter().enumerate() {
+        if let (true, Some(hash)) = (size_counts[&entry.size] > 1, &entry.content_hash) {
+            by_hash.entry(hash).or_default().push(index);
+        }
+    }
+    Some(by_hash.into_values().filter(|group| group.len() > 1).collect())
+}
+
+/// Computes the perceptual hashes missing from entries, e.g. of thumbnails that were served
+/// from the cache, by decoding their sources. Returns false if the job was cancelled.
+fn fill_phashes(job: &Job, entries: &mut [&mut CatalogEntry]) -> bool {
+    par_batches(job, entries, |entry| {
+        let Some(kind) = entry.mime_type.as_deref().and_then(MediaKind::from_mime_type) else {
+            return;
+        };
+        match image_processor::source_phash(&entry.path, kind) {
+            Ok(phash) => {
+                if let Err(e) = catalog::set_phash(entry, phash) {
+                    println!("Failed to store perceptual hash of {}: {}", entry.path, e);
+                }
+                entry.phash = Some(phash);
+            }
+            Err(e) => println!("Failed to hash {}: {}", entry.path, e),
+        }
+    })
+}
+
+/// Groups of entries among `candidates` whose perceptual hashes are within `max_distance` bits
+/// of the group's keeper, which comes first. Groups form around the best copies first, so
+/// chains of similar images never pull in members far from the keeper.
+fn similar_groups(entries: &[CatalogEntry], candidates: &[usize], max_distance: u32) -> Vec<Vec<usize>> {
+    let mut hashed: Vec<(usize, u64)> = candidates
+        .iter()
+        .filter_map(|&index| entries[index].phash.map(|phash| (index, phash)))
+        .collect();
+    hashed.sort_by_cached_key(|&(index, _)| Reverse(keeper_rank(&entries[index])));
+    let mut tree = BkTree::default();
+    for (position, &(_, phash)) in hashed.iter().enumerate() {
+        tree.insert(phash, position);
+    }
+
+    let mut grouped = vec![false; hashed.len()];
+    let mut groups = Vec::new();
+    for (position, &(_, phash)) in hashed.iter().enumerate() {
+        if grouped[position] {
+            continue;
+        }
+        let mut members: Vec<usize> = tree
+            .within(phash, max_distance)
+            .into_iter()
+            .filter(|&other| other != position && !grouped[other])
+            .collect();
+        if members.is_empty() {
+            continue;
+        }
+        members.sort();
+        members.insert(0, position);
+        for &member in &members {
+            grouped[member] = true;
+        }
+        groups.push(members.into_iter().map(|member| hashed[member].0).collect());
+    }
+    groups
+}
+
+fn group_json(kind: &str, entries: &[CatalogEntry], keeper: usize, group: &[usize]) -> Value {
+    let reclaimable: u64 = group
+        .iter()
+        .filter(|&&index| index != keeper)
+        .map(|&index| entries[index].size)
+        .sum();
+
+    let items: Vec<Value> = group
+        .iter()
+        .map(|&index| {
+            let entry = &entries[index];
+            let mut item = entry.to_media_item();
+            item["size"] = json!(entry.size);
+            if let (Some(phash), Some(keeper_phash)) = (entry.phash, entries[keeper].phash) {
+                // Bits from the keeper's perceptual hash; 0 for exact copies
+                item["distance"] = json!(distance(phash, keeper_phash));
+            }
+            item
+        })
+        .collect();
+    json!({
+        "kind": kind,
+        "keeper": entries[keeper].id,
+        "reclaimable_bytes": reclaimable,
+        "items": items
+    })
+}
+
+/// Finds duplicate images in the catalog as part of `job`. Exact groups hold files with
+/// identical contents; similar groups hold distinct contents within `max_distance` bits of
+/// perceptual hash, with each exact group taking part through its keeper. Largest savings first.
+pub fn find(job: &Job, max_distance: u32) -> Result<Value, String> {
+    let cancelled = || "Duplicate search cancelled".to_string();
+    // Files changed or removed since their last scan are left out until it catalogues them again
+    let mut entries: Vec<CatalogEntry> = catalog::all_entries()?
+        .into_par_iter()
+        .filter(|entry| entry.media_type == "image" && !job.is_cancelled())
+        .filter(|entry| current_metadata(&entry.path) == Some((entry.size, entry.mtime)))
+        .collect();
+    if !job.checkpoint() {
+        return Err(cancelled());
+    }
+
+    let exact = exact_groups(job, &mut entries).ok_or_else(cancelled)?;
+    let mut copies = vec![false; entries.len()];
+    for group in &exact {
+        let keeper = keeper(&entries, group);
+        for &index in group.iter().filter(|&&index| index != keeper) {
+            copies[index] = true;
+        }
+    }
+    let distinct: Vec<usize> = (0..entries.len()).filter(|&index| !copies[index]).collect();
+    let mut unhashed: Vec<&mut CatalogEntry> = entries
+        .iter_mut()
+        .enumerate()
+        .filter(|(index, entry)| !copies[*index] && entry.phash.is_none())
+        .map(|(_, entry)| entry)
+        .collect();
+    if !fill_phashes(job, &mut unhashed) {
+        return Err(cancelled());
+    }
+    let similar = similar_groups(&entries, &distinct, max_distance);
+
+    let mut groups: Vec<Value> = exact
+        .iter()
+        .map(|group| group_json("exact", &entries, keeper(&entries, group), group))
+        .chain(similar.iter().map(|group| group_json("similar", &entries, group[0], group)))
+        .collect();
+    groups.sort_by_key(|group| Reverse(group["reclaimable_bytes"].as_u64().unwrap_or(0)));
+
+    Ok(json!({
+        "groups": groups,
+        "max_distance": max_distance
+    }))
+}
+
+/// Groups catalogued images that are exact or near duplicates, naming the best-quality copy
+/// of each group as its `keeper`. `max_distance` (0-64 bits) overrides the config's
+/// `duplicates.max_distance`. Runs as a `duplicates` job, which a new search supersedes.
+#[tauri::command]
+pub async fn find_duplicates(app: AppHandle, max_distance: Option<u32>) -> Result<Value, String> {
+    let max_distance = match max_distance {
+        Some(distance) => distance.min(64),
+        None => crate::initialise::fetch_config()
+            .await
+            .map(|config| self::max_distance(&config))
+            .unwrap_or(DEFAULT_MAX_DISTANCE),
+    };
+    jobs::cancel_kind("duplicates");
+    let job = jobs::create(&app, "duplicates", "Finding duplicate images".to_string());
+    tokio::task::spawn_blocking(move || {
+        if !job.run() {
+            return Err("Duplicate search cancelled".to_string());
+        }
+        let result = find(&job, max_distance);
+        job.complete();
+        result
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brute_force(hashes: &[u64], hash: u64, max_distance: u32) -> Vec<usize> {
+        (0..hashes.len())
+            .filter(|&index| distance(hashes[index], hash) <= max_distance)
+            .collect()
+    }
+
+    fn entry(path: &str, size: u64, phash: u64) -> CatalogEntry {
+        CatalogEntry {
+            id: path.to_string(),
+            path: path.to_string(),
+            size,
+            mtime: 0,
+            media_type: "image".to_string(),
+            mime_type: Some("image/png".to_string()),
+            extension_mismatch: false,
+            width: Some(100),
+            height: Some(100),
+            orientation: Some(1),
+            frame_count: Some(1),
+            duration_ms: Some(0),
+            placeholder: None,
+            phash: Some(phash),
+            content_hash: None,
+            thumbnail_key: None,
+            thumbnail_error: None,
+            thumbnail_version: None,
+            dev: None,
+            inode: None,
+        }
+    }
+
+    #[test]
+    fn similar_groups_do_not_chain() {
+        // b is within 4 bits of both a and c, but c is 8 bits from a
+        let entries = vec![entry("c.png", 100, 0xFF), entry("a.png", 300, 0x00), entry("b.png", 200, 0x0F)];
+        let groups = similar_groups(&entries, &[0, 1, 2], 5);
+        assert_eq!(groups, vec![vec![1, 2]]);
+        for group in &groups {
+            let keeper_phash = entries[group[0]].phash.unwrap();
+            assert!(group.iter().all(|&index| distance(entries[index].phash.unwrap(), keeper_phash) <= 5));
+        }
+        // With room for c, the keeper gathers all three
+        assert_eq!(similar_groups(&entries, &[0, 1, 2], 8), vec![vec![1, 2, 0]]);
+    }
+
+    #[test]
+    fn bk_tree_matches_a_linear_scan() {
+        // A fixed xorshift sequence, with near copies of some hashes mixed in
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut hashes = Vec::new();
+        for index in 0..400 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            hashes.push(match index % 4 {
+                3 => hashes[index - 1] ^ (state & 0x0101_0101),
+                _ => state,
+            });
+        }
+
+        let mut tree = BkTree::default();
+        for (index, &hash) in hashes.iter().enumerate() {
+            tree.insert(hash, index);
+        }
+        for max_distance in [0, 3, 10, 28] {
+            for &hash in hashes.iter().step_by(7) {
+                let mut found = tree.within(hash, max_distance);
+                found.sort();
+                assert_eq!(found, brute_force(&hashes, hash, max_distance));
+            }
+        }
+    }
+
+    #[test]
+    fn dhash_ignores_the_decode_size() {
+        let source = |width: u32, height: u32| {
+            DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+                let (x, y) = (x * 640 / width, y * 480 / height);
+                image::Rgb([((x * y) % 251) as u8, (x / 3) as u8, (255 - y / 2) as u8])
+            }))
+        };
+        let full = dhash(&source(640, 480));
+        assert!(distance(full, dhash(&source(160, 120))) <= 2);
+        assert!(distance(full, dhash(&source(80, 60))) <= 4);
+    }
+
+    #[test]
+    fn bk_tree_keeps_repeated_hashes() {
+        let mut tree = BkTree::default();
+        assert!(tree.within(0, 64).is_empty());
+        for index in 0..3 {
+            tree.insert(0xFF, index);
+        }
+        tree.insert(0xFE, 3);
+        let mut found = tree.within(0xFF, 0);
+        found.sort();
+        assert_eq!(found, vec![0, 1, 2]);
+        assert_eq!(tree.within(0x01, 7).len(), 3);
+        assert_eq!(tree.within(0x01, 8).len(), 4);
+    }
+}
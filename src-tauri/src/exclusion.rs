@@ -0,0 +1,250 @@
+use crate::scan_settings::{enabled_roots, RootSettings};
+use crate::utils::expand_paths;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{Walk, WalkBuilder};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const SNAPHOUND_IGNORE_FILE: &str = ".snaphoundignore";
+// Used when the config has no `exclude` section yet
+const DEFAULT_PATTERNS: [&str; 9] = [
+    ".git",
+    "node_modules",
+    ".cache",
+    "__pycache__",
+    ".venv",
+    "venv",
+    "target",
+    "build",
+    "dist",
+];
+
+/// Gitignore-style exclusion rules shared by the scanner, the watcher and the indexer.
+///
+/// Config shape:
+/// ```json
+/// "exclude": {
+///     "patterns": ["node_modules", "*.tmp"],
+///     "roots": { "~/Documents/*": ["drafts/"] },
+///     "skip_hidden": true,
+///     "use_ignore_files": true
+/// }
+/// ```
+pub struct ExclusionRules {
+    global: Gitignore,
+    per_root: Vec<Gitignore>,
+    roots: Vec<PathBuf>,
+    // Roots whose `include_hidden` differs from `skip_hidden`
+    hidden_overrides: Vec<(PathBuf, bool)>,
+    skip_hidden: bool,
+    use_ignore_files: bool,
+    // Ignore files by directory for `is_excluded`, with the mtimes they were parsed at
+    ignore_files: Mutex<HashMap<PathBuf, IgnoreFiles>>,
+}
+
+struct IgnoreFiles {
+    modified: [Option<SystemTime>; 2],
+    matcher: Option<Arc<Gitignore>>,
+}
+
+impl ExclusionRules {
+    pub fn from_config(config: &Value) -> Self {
+        let exclude = &config["exclude"];
+        let patterns: Vec<String> = match exclude["patterns"].as_array() {
+            Some(patterns) => patterns
+                .iter()
+                .filter_map(|p| p.as_str().map(String::from))
+                .collect(),
+            None => DEFAULT_PATTERNS.iter().map(|p| p.to_string()).collect(),
+        };
+
+        // Matched against root-relative paths, so `node_modules` hits at any depth below a root
+        let global = build_matcher(Path::new("."), &patterns);
+
+        let mut per_root = Vec::new();
+        if let Some(roots) = exclude["roots"].as_object() {
+            for (root, patterns) in roots {
+                let patterns: Vec<String> = serde_json::from_value(patterns.clone()).unwrap_or_default();
+                for directory in expand_paths(vec![root.clone()]) {
+                    per_root.push(build_matcher(&directory, &patterns));
+                }
+            }
+        }
+
+        let skip_hidden = exclude["skip_hidden"].as_bool().unwrap_or(true);
+        let mut roots = Vec::new();
+        let mut hidden_overrides = Vec::new();
+        for settings in enabled_roots(config) {
+            let directories = expand_paths(vec![settings.path.clone()]);
+            if let Some(include_hidden) = settings.include_hidden {
+                hidden_overrides.extend(directories.iter().map(|d| (d.clone(), !include_hidden)));
+            }
+            roots.extend(directories);
+        }
+
+        Self {
+            global,
+            per_root,
+            roots,
+            hidden_overrides,
+            skip_hidden,
+            use_ignore_files: exclude["use_ignore_files"].as_bool().unwrap_or(true),
+            ignore_files: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Walks `root` honoring every rule, including `.gitignore`/`.snaphoundignore` files, and
+    /// the depth, symlink and hidden-file settings of the priority path it came from.
+    pub fn walker(rules: &Arc<Self>, root: &Path, settings: &RootSettings) -> Walk {
+        let skip_hidden = settings
+            .include_hidden
+            .map_or(rules.skip_hidden, |include_hidden| !include_hidden);
+        let mut builder = WalkBuilder::new(root);
+        builder
+            .standard_filters(false)
+            .hidden(skip_hidden)
+            .max_depth(settings.max_depth)
+            .follow_links(settings.follow_symlinks)
+            .sort_by_file_path(|a, b| a.cmp(b));
+
+        if rules.use_ignore_files {
+            builder
+                .git_ignore(true)
+                .parents(true)
+                // Honor .gitignore even outside of a git checkout
+                .require_git(false)
+                .add_custom_ignore_filename(SNAPHOUND_IGNORE_FILE);
+        }
+
+        let rules = Arc::clone(rules);
+        builder.filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            entry.depth() == 0 || !rules.matches_patterns(entry.path(), is_dir)
+        });
+        builder.build()
+    }
+
+    /// Checks a single path against the configured patterns, the ignore files above it and the
+    /// hidden-file toggle, like `walker` would.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if self.matches_patterns(path, is_dir) || (self.use_ignore_files && self.ignored_by_files(path, is_dir)) {
+            return true;
+        }
+
+        // Only components below the configured root count, the root itself may be hidden
+        self.skips_hidden(path)
+            && self
+                .relative_to_root(path)
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+    }
+
+    /// Whether the `.gitignore`/`.snaphoundignore` files of the directories above `path` ignore
+    /// it. The nearest file with a matching rule decides, as while walking.
+    fn ignored_by_files(&self, path: &Path, is_dir: bool) -> bool {
+        for directory in path.ancestors().skip(1) {
+            let Some(matcher) = self.ignore_matcher(directory) else {
+                continue;
+            };
+            let matched = matcher.matched_path_or_any_parents(path, is_dir);
+            if matched.is_ignore() || matched.is_whitelist() {
+                return matched.is_ignore();
+            }
+        }
+        false
+    }
+
+    /// The rules of `directory`'s ignore files, reparsed when either changes. `.snaphoundignore`
+    /// is added last so it overrides `.gitignore`.
+    fn ignore_matcher(&self, directory: &Path) -> Option<Arc<Gitignore>> {
+        let files = [directory.join(".gitignore"), directory.join(SNAPHOUND_IGNORE_FILE)];
+        let modified = files
+            .each_ref()
+            .map(|file| fs::metadata(file).and_then(|metadata| metadata.modified()).ok());
+
+        let mut cache = self.ignore_files.lock().unwrap();
+        if let Some(cached) = cache.get(directory).filter(|cached| cached.modified == modified) {
+            return cached.matcher.clone();
+        }
+
+        let matcher = modified.iter().any(Option::is_some).then(|| {
+            let mut builder = GitignoreBuilder::new(directory);
+            for (file, _) in files.iter().zip(&modified).filter(|(_, modified)| modified.is_some()) {
+                if let Some(e) = builder.add(file) {
+                    println!("Failed to read {}: {}", file.display(), e);
+                }
+            }
+            Arc::new(builder.build().unwrap_or_else(|e| {
+                println!("Failed to build ignore rules for {}: {}", directory.display(), e);
+                Gitignore::empty()
+            }))
+        });
+        cache.insert(
+            directory.to_path_buf(),
+            IgnoreFiles {
+                modified,
+                matcher: matcher.clone(),
+            },
+        );
+        matcher
+    }
+
+    fn skips_hidden(&self, path: &Path) -> bool {
+        self.hidden_overrides
+            .iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .min_by_key(|(root, _)| root.components().count())
+            .map_or(self.skip_hidden, |(_, skip)| *skip)
+    }
+
+    fn relative_to_root<'a>(&self, path: &'a Path) -> &'a Path {
+        self.roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .min_by_key(|root| root.components().count())
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path)
+    }
+
+    fn matches_patterns(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = self.relative_to_root(path);
+        if !relative.has_root() && self.global.matched_path_or_any_parents(relative, is_dir).is_ignore() {
+            return true;
+        }
+
+        self.per_root
+            .iter()
+            .filter(|matcher| path.starts_with(matcher.path()))
+            .any(|matcher| matcher.matched_path_or_any_parents(path, is_dir).is_ignore())
+    }
+}
+
+/// The `exclude` section handed to the indexer, with defaults filled in.
+pub fn indexer_args(config: &Value) -> Value {
+    let exclude = &config["exclude"];
+    json!({
+        "patterns": exclude.get("patterns").cloned().unwrap_or_else(|| json!(DEFAULT_PATTERNS)),
+        "roots": exclude.get("roots").cloned().unwrap_or_else(|| json!({})),
+        "skip_hidden": exclude["skip_hidden"].as_bool().unwrap_or(true),
+        "use_ignore_files": exclude["use_ignore_files"].as_bool().unwrap_or(true),
+        "ignore_files": [".gitignore", SNAPHOUND_IGNORE_FILE]
+    })
+}
+
+fn build_matcher(root: &Path, patterns: &[String]) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        if let Err(e) = builder.add_line(None, pattern) {
+            println!("Invalid exclude pattern {:?}: {}", pattern, e);
+        }
+    }
+
+    builder.build().unwrap_or_else(|e| {
+        println!("Failed to build exclude rules: {}", e);
+        Gitignore::empty()
+    })
+}
@@ -0,0 +1,315 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+pub const TAG_COMPRESSION: u16 = 0x0103;
+pub const TAG_PHOTOMETRIC: u16 = 0x0106;
+pub const TAG_STRIP_OFFSETS: u16 = 0x0111;
+pub const TAG_ORIENTATION: u16 = 0x0112;
+pub const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+pub const TAG_SUB_IFDS: u16 = 0x014A;
+pub const TAG_JPEG_OFFSET: u16 = 0x0201;
+pub const TAG_JPEG_LENGTH: u16 = 0x0202;
+pub const TAG_ICC_PROFILE: u16 = 0x8773;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// Corrupt files can claim absurd entry counts
+const MAX_ENTRIES: u16 = 1024;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_IFD: u16 = 13;
+
+/// One 12-byte IFD entry; `value` holds the value itself or, when it does not fit, its offset.
+pub struct IfdEntry {
+    pub tag: u16,
+    pub field_type: u16,
+    pub count: u32,
+    value: [u8; 4],
+}
+
+/// Minimal reader for the TIFF structure shared by EXIF blocks and TIFF-based RAW formats.
+/// Offsets are relative to `base`, where the `II`/`MM` byte order mark sits.
+pub struct TiffReader<R> {
+    reader: R,
+    base: u64,
+    little_endian: bool,
+}
+
+impl<R: Read + Seek> TiffReader<R> {
+    /// `None` unless a TIFF header starts at `base`. Panasonic's `IIU` variant is accepted.
+    pub fn new(mut reader: R, base: u64) -> Option<Self> {
+        let mut header = [0u8; 2];
+        reader.seek(SeekFrom::Start(base)).ok()?;
+        reader.read_exact(&mut header).ok()?;
+        let little_endian = match &header {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self {
+            reader,
+            base,
+            little_endian,
+        })
+    }
+
+    pub fn first_ifd(&mut self) -> Option<u64> {
+        let mut offset = [0u8; 4];
+        self.read_at(4, &mut offset)?;
+        Some(self.u32(&offset) as u64)
+    }
+
+    /// Reads `buf.len()` bytes at `offset` (relative to the TIFF header).
+    pub fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> Option<()> {
+        self.reader.seek(SeekFrom::Start(self.base + offset)).ok()?;
+        self.reader.read_exact(buf).ok()
+    }
+
+    /// The entries of the IFD at `offset` and the offset of the next IFD (0 at the end).
+    pub fn read_ifd(&mut self, offset: u64) -> Option<(Vec<IfdEntry>, u64)> {
+        let mut count = [0u8; 2];
+        self.read_at(offset, &mut count)?;
+        let count = self.u16(&count).min(MAX_ENTRIES) as usize;
+
+        let mut raw = vec![0u8; count * 12 + 4];
+        self.read_at(offset + 2, &mut raw)?;
+        let entries = raw[..count * 12]
+            .chunks_exact(12)
+            .map(|entry| IfdEntry {
+                tag: self.u16(&entry[0..2]),
+                field_type: self.u16(&entry[2..4]),
+                count: self.u32(&entry[4..8]),
+                value: [entry[8], entry[9], entry[10], entry[11]],
+            })
+            .collect();
+        Some((entries, self.u32(&raw[count * 12..]) as u64))
+    }
+
+    /// First value of a SHORT/LONG entry.
+    pub fn scalar(&self, entries: &[IfdEntry], tag: u16) -> Option<u32> {
+        let entry = entries.iter().find(|entry| entry.tag == tag)?;
+        match entry.field_type {
+            TYPE_SHORT => Some(self.u16(&entry.value[..2]) as u32),
+            TYPE_LONG | TYPE_IFD => Some(self.u32(&entry.value)),
+            _ => None,
+        }
+    }
+
+    /// Offset of an entry's out-of-line data, e.g. an opaque embedded JPEG.
+    pub fn value_offset(&self, entry: &IfdEntry) -> u64 {
+        self.u32(&entry.value) as u64
+    }
+
+    /// Every value of a LONG/IFD entry such as the SubIFD offsets, up to `limit`.
+    pub fn longs(&mut self, entries: &[IfdEntry], tag: u16, limit: usize) -> Vec<u64> {
+        let Some(entry) = entries
+            .iter()
+            .find(|entry| entry.tag == tag && matches!(entry.field_type, TYPE_LONG | TYPE_IFD))
+        else {
+            return Vec::new();
+        };
+        let count = (entry.count as usize).min(limit);
+        // A single value is stored in the entry itself, more sit at its offset
+        if entry.count <= 1 {
+            return (count == 1).then(|| self.u32(&entry.value) as u64).into_iter().collect();
+        }
+
+        let mut values = vec![0u8; count * 4];
+        match self.read_at(self.u32(&entry.value) as u64, &mut values) {
+            Some(()) => values.chunks_exact(4).map(|value| self.u32(value) as u64).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+}
+
+/// Orientation (1-8) stored in IFD0 of a TIFF structure.
+pub fn tiff_orientation<R: Read + Seek>(tiff: &mut TiffReader<R>) -> Option<u32> {
+    let offset = tiff.first_ifd()?;
+    let (entries, _) = tiff.read_ifd(offset)?;
+    tiff.scalar(&entries, TAG_ORIENTATION).filter(|o| (1..=8).contains(o))
+}
+
+/// Orientation (1-8) from the EXIF APP1 segment of an in-memory JPEG.
+pub fn jpeg_orientation(jpeg: &[u8]) -> Option<u32> {
+    let exif_start = jpeg_segments(jpeg)
+        .find(|(marker, _, payload)| *marker == 0xE1 && payload.starts_with(EXIF_HEADER))
+        .map(|(_, offset, _)| offset + EXIF_HEADER.len())?;
+    let mut tiff = TiffReader::new(Cursor::new(jpeg), exif_start as u64)?;
+    tiff_orientation(&mut tiff)
+}
+
+/// Orientation (1-8) of an in-memory JPEG, PNG (`eXIf`), WebP (`EXIF`) or TIFF.
+pub fn orientation(data: &[u8]) -> Option<u32> {
+    let exif_block = match data {
+        [0xFF, 0xD8, ..] => return jpeg_orientation(data),
+        [0x89, b'P', b'N', b'G', ..] => png_chunks(data).find(|(kind, _)| kind == b"eXIf")?.1,
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => {
+            riff_chunks(data).find(|(id, _)| id == b"EXIF")?.1
+        }
+        [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => data,
+        _ => return None,
+    };
+    // Some writers keep the JPEG-style header in front of the TIFF block
+    let exif_block = exif_block.strip_prefix(EXIF_HEADER).unwrap_or(exif_block);
+    let mut tiff = TiffReader::new(Cursor::new(exif_block), 0)?;
+    tiff_orientation(&mut tiff)
+}
+
+/// Whether an in-memory JPEG uses a baseline or progressive (DCT) frame, which regular decoders
+/// handle. RAW files also carry lossless JPEG sensor data, which must be skipped.
+pub fn is_dct_jpeg(jpeg: &[u8]) -> bool {
+    jpeg_segments(jpeg)
+        .find(|(marker, _, _)| is_start_of_frame(*marker))
+        .is_some_and(|(marker, _, _)| matches!(marker, 0xC0..=0xC2))
+}
+
+fn is_start_of_frame(marker: u8) -> bool {
+    matches!(marker, 0xC0..=0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF)
+}
+
+/// The marker segments of a JPEG up to the start of the scan data, as (marker, payload offset,
+/// payload).
+pub fn jpeg_segments(jpeg: &[u8]) -> impl Iterator<Item = (u8, usize, &[u8])> {
+    let mut position = if jpeg.starts_with(&[0xFF, 0xD8]) { 2 } else { jpeg.len() };
+    std::iter::from_fn(move || {
+        let marker = *jpeg.get(position + 1).filter(|_| jpeg[position] == 0xFF)?;
+        let length = u16::from_be_bytes([*jpeg.get(position + 2)?, *jpeg.get(position + 3)?]) as usize;
+        let start = position + 4;
+        let payload = jpeg.get(start..position + 2 + length)?;
+        // Entropy-coded data follows SOS; nothing after it is a header
+        position = if marker == 0xDA { jpeg.len() } else { position + 2 + length };
+        Some((marker, start, payload))
+    })
+}
+
+/// The chunks of a PNG as (type, data).
+pub fn png_chunks(png: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut position = if png.starts_with(PNG_SIGNATURE) { PNG_SIGNATURE.len() } else { png.len() };
+    std::iter::from_fn(move || {
+        let length = u32::from_be_bytes(png.get(position..position + 4)?.try_into().ok()?) as usize;
+        let kind = png.get(position + 4..position + 8)?.try_into().ok()?;
+        let data = png.get(position + 8..position + 8 + length)?;
+        // Length, type, data and CRC
+        position += 12 + length;
+        Some((kind, data))
+    })
+}
+
+/// The chunks of a RIFF container such as WebP, as (FourCC, data).
+pub fn riff_chunks(riff: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut position = if riff.starts_with(b"RIFF") { 12 } else { riff.len() };
+    std::iter::from_fn(move || {
+        let id = riff.get(position..position + 4)?.try_into().ok()?;
+        let length = u32::from_le_bytes(riff.get(position + 4..position + 8)?.try_into().ok()?) as usize;
+        let data = riff.get(position + 8..position + 8 + length)?;
+        // Odd-sized chunks are padded to an even length
+        position += 8 + length + (length & 1);
+        Some((id, data))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian TIFF with one IFD at offset 8 holding (tag, type, count, value) entries.
+    fn tiff(entries: &[(u16, u16, u32, u32)]) -> Vec<u8> {
+        let mut bytes = b"II\x2A\x00\x08\x00\x00\x00".to_vec();
+        bytes.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for &(tag, field_type, count, value) in entries {
+            bytes.extend_from_slice(&tag.to_le_bytes());
+            bytes.extend_from_slice(&field_type.to_le_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0; 4]);
+        bytes
+    }
+
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0xFF, marker];
+        bytes.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn reads_little_endian_ifd() {
+        let mut data = tiff(&[(TAG_ORIENTATION, TYPE_SHORT, 1, 6), (TAG_SUB_IFDS, TYPE_LONG, 2, 38)]);
+        data.extend_from_slice(&[0x00, 0x01, 0, 0, 0x00, 0x02, 0, 0]);
+        let mut reader = TiffReader::new(Cursor::new(&data), 0).unwrap();
+
+        let offset = reader.first_ifd().unwrap();
+        let (entries, next) = reader.read_ifd(offset).unwrap();
+        assert_eq!((offset, entries.len(), next), (8, 2, 0));
+        assert_eq!(reader.scalar(&entries, TAG_ORIENTATION), Some(6));
+        assert_eq!(reader.longs(&entries, TAG_SUB_IFDS, 8), vec![256, 512]);
+        assert_eq!(reader.longs(&entries, TAG_SUB_IFDS, 1), vec![256]);
+        assert_eq!(orientation(&data), Some(6));
+    }
+
+    #[test]
+    fn reads_big_endian_orientation_at_an_offset() {
+        // EXIF blocks sit behind a base offset; values are left-aligned in the entry
+        let mut data = vec![0xAA; 3];
+        data.extend_from_slice(b"MM\x00\x2A\x00\x00\x00\x08\x00\x01\x01\x12\x00\x03\x00\x00\x00\x01\x00\x03\x00\x00");
+        data.extend_from_slice(&[0; 4]);
+        let mut reader = TiffReader::new(Cursor::new(&data), 3).unwrap();
+        assert_eq!(tiff_orientation(&mut reader), Some(3));
+        assert!(TiffReader::new(Cursor::new(&data), 0).is_none());
+    }
+
+    #[test]
+    fn rejects_out_of_range_orientation() {
+        assert_eq!(orientation(&tiff(&[(TAG_ORIENTATION, TYPE_SHORT, 1, 9)])), None);
+    }
+
+    #[test]
+    fn walks_jpeg_segments_up_to_the_scan() {
+        let exif_block = [EXIF_HEADER, &tiff(&[(TAG_ORIENTATION, TYPE_SHORT, 1, 8)])].concat();
+        let jpeg = [
+            vec![0xFF, 0xD8],
+            segment(0xE0, b"JFIF\0"),
+            segment(0xE1, &exif_block),
+            segment(0xC2, &[8, 0, 1, 0, 1, 1]),
+            segment(0xDA, &[0]),
+            // Entropy-coded data that happens to look like a marker
+            vec![0xFF, 0xE1, 0x00, 0x04, 0x00, 0x00],
+        ]
+        .concat();
+
+        let segments: Vec<(u8, usize)> = jpeg_segments(&jpeg).map(|(marker, offset, _)| (marker, offset)).collect();
+        let end = 15 + exif_block.len();
+        assert_eq!(segments, vec![(0xE0, 6), (0xE1, 15), (0xC2, end + 4), (0xDA, end + 14)]);
+        assert_eq!(jpeg_orientation(&jpeg), Some(8));
+        assert_eq!(orientation(&jpeg), Some(8));
+        assert!(is_dct_jpeg(&jpeg));
+    }
+
+    #[test]
+    fn tells_lossless_jpegs_apart() {
+        let lossless = [vec![0xFF, 0xD8], segment(0xC3, &[8, 0, 1, 0, 1, 1])].concat();
+        assert!(!is_dct_jpeg(&lossless));
+        // Truncated segments end the walk instead of reading past the end
+        assert_eq!(jpeg_segments(&lossless[..6]).count(), 0);
+        assert_eq!(jpeg_segments(b"not a jpeg").count(), 0);
+    }
+}
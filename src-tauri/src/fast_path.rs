@@ -0,0 +1,76 @@
+// The thumbnail fast path: JPEGs decoded at a fraction of their size through libjpeg's DCT
+// scaling, and SIMD resizing. Only depends on external crates so the decode benchmark can
+// include it directly.
+use fast_image_resize as fr;
+use image::imageops::FilterType;
+use image::{DynamicImage, RgbImage, RgbaImage};
+use std::num::NonZeroU32;
+use std::panic::{self, AssertUnwindSafe};
+
+// libjpeg scales by n/8; 8/8 is a regular full decode
+const DCT_SCALES: [u8; 3] = [1, 2, 4];
+
+/// Decodes a JPEG at the smallest DCT scale (1/8, 1/4 or 1/2) that is still at least
+/// `min_size(full size)` in both dimensions. Returns the image and the JPEG's full size, or
+/// `None` when only a full decode would do or libjpeg can't decode it (e.g. CMYK).
+pub fn decode_jpeg_scaled(
+    data: &[u8],
+    min_size: impl FnOnce((u32, u32)) -> (u32, u32),
+) -> Option<(DynamicImage, (u32, u32))> {
+    // mozjpeg reports errors by unwinding
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut decompress = mozjpeg::Decompress::new_mem(data).ok()?;
+        let (width, height) = decompress.size();
+        let full_size = (width as u32, height as u32);
+        let (min_width, min_height) = min_size(full_size);
+
+        let scaled = |length: u32, numerator: u8| (length * numerator as u32).div_ceil(8);
+        let numerator = DCT_SCALES.into_iter().find(|&numerator| {
+            scaled(full_size.0, numerator) >= min_width && scaled(full_size.1, numerator) >= min_height
+        })?;
+        decompress.scale(numerator);
+
+        let mut started = decompress.rgb().ok()?;
+        let (width, height) = (started.width() as u32, started.height() as u32);
+        let pixels = started.read_scanlines::<u8>().ok()?;
+        started.finish().ok()?;
+        let img = RgbImage::from_raw(width, height, pixels)?;
+        Some((DynamicImage::ImageRgb8(img), full_size))
+    }))
+    .ok()
+    .flatten()
+}
+
+/// Resizes to exactly `width` x `height` with `fast_image_resize`. Returns `None` for filters
+/// it doesn't have (Gaussian), so the caller can fall back to `image`.
+pub fn resize(img: &DynamicImage, width: u32, height: u32, filter: FilterType) -> Option<DynamicImage> {
+    let algorithm = match filter {
+        FilterType::Nearest => fr::ResizeAlg::Nearest,
+        FilterType::Triangle => fr::ResizeAlg::Convolution(fr::FilterType::Bilinear),
+        FilterType::CatmullRom => fr::ResizeAlg::Convolution(fr::FilterType::CatmullRom),
+        FilterType::Lanczos3 => fr::ResizeAlg::Convolution(fr::FilterType::Lanczos3),
+        FilterType::Gaussian => return None,
+    };
+    let (dst_width, dst_height) = (NonZeroU32::new(width)?, NonZeroU32::new(height)?);
+    let (src_width, src_height) = (NonZeroU32::new(img.width())?, NonZeroU32::new(img.height())?);
+    let mut resizer = fr::Resizer::new(algorithm);
+
+    // Thumbnails are 8-bit, so deeper sources are converted down first
+    if img.color().has_alpha() {
+        // Colors are premultiplied by alpha while resizing so transparent pixels don't bleed
+        let mul_div = fr::MulDiv::default();
+        let mut src = fr::Image::from_vec_u8(src_width, src_height, img.to_rgba8().into_raw(), fr::PixelType::U8x4)
+            .ok()?;
+        mul_div.multiply_alpha_inplace(&mut src.view_mut()).ok()?;
+        let mut dst = fr::Image::new(dst_width, dst_height, fr::PixelType::U8x4);
+        resizer.resize(&src.view(), &mut dst.view_mut()).ok()?;
+        mul_div.divide_alpha_inplace(&mut dst.view_mut()).ok()?;
+        RgbaImage::from_raw(width, height, dst.into_vec()).map(DynamicImage::ImageRgba8)
+    } else {
+        let src = fr::Image::from_vec_u8(src_width, src_height, img.to_rgb8().into_raw(), fr::PixelType::U8x3)
+            .ok()?;
+        let mut dst = fr::Image::new(dst_width, dst_height, fr::PixelType::U8x3);
+        resizer.resize(&src.view(), &mut dst.view_mut()).ok()?;
+        RgbImage::from_raw(width, height, dst.into_vec()).map(DynamicImage::ImageRgb8)
+    }
+}
@@ -1,174 +1,471 @@
+use crate::archive;
+use crate::catalog::{self, CatalogEntry};
+use crate::exclusion::ExclusionRules;
 use crate::image_processor::process_thumbnail;
+use crate::media_id::{file_identity, media_id};
 use crate::initialise::fetch_config;
-use crate::utils::{expand_paths, get_file_type, send_to_frontend};
+use crate::jobs::{self, Job};
+use crate::media_kind::{MediaKind, MediaType};
+use crate::scan_progress::{ItemOutcome, ScanProgress};
+use crate::scan_settings::{enabled_roots, RootSettings};
+use crate::thumbnail_cache;
+use crate::thumbnail_profile;
+use crate::thumbnail_queue::{self, Batch};
+use crate::watcher;
+use crate::utils::{
+    expand_priority_paths, get_file_type, modified_nanos, outermost_directories, send_to_frontend,
+};
 use rayon::prelude::*;
 use serde_json::{json, Value};
 use std::collections::HashSet;
+use std::fs;
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::AtomicUsize;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use tauri::AppHandle;
-use uuid::Uuid;
-use walkdir::WalkDir;
 use dashmap::DashSet;
 
-lazy_static::lazy_static! {
-    static ref CANCEL_FLAG: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
-    static ref SENT_FILES: DashSet<String> = DashSet::new();
+/// State of one `list_files` run. Each scan is its own job, so overlapping scans and searches
+/// no longer share cancellation or dedup state.
+struct ScanRun {
+    app: AppHandle,
+    job: Arc<Job>,
+    progress: Arc<ScanProgress>,
+    // Thumbnails queued by this scan, which it waits for before finishing
+    thumbnails: Arc<Batch>,
+    // Files already emitted by this scan, for roots that overlap
+    sent_files: DashSet<String>,
+    stream_items: bool,
 }
 
+/// A media file found by a scan. Archive members carry the size and mtime from their listing.
+struct Found {
+    path: PathBuf,
+    media: MediaType,
+    metadata: Option<(u64, u64)>,
+}
+
+/// Scans the priority paths into the catalog as a `scan` job and returns the job id. With
+/// `stream_items` (the default) every item is also pushed as `file_path` events; without it
+/// only `catalog_updated` counts are sent and the grid pages through `query_media` instead.
 #[tauri::command]
-pub async fn list_files(app: AppHandle) {
-    CANCEL_FLAG.store(false, Ordering::SeqCst);
+pub async fn list_files(app: AppHandle, stream_items: Option<bool>) -> String {
+    let stream_items = stream_items.unwrap_or(true);
     let app_clone = app.clone();
     let config = crate::initialise::fetch_config().await.unwrap();
+    thumbnail_profile::load(&app, &config);
+    // A new scan supersedes any running one, so repeated calls never pile up full scans
+    jobs::cancel_kind("scan");
+    let job = jobs::create(&app, "scan", "Scanning priority paths".to_string());
+    let job_id = job.id.clone();
     thread::spawn(move || {
-        let priority_paths: Vec<String> =
-            serde_json::from_value(config["priority_paths"].clone()).unwrap_or_default();
-        if priority_paths.is_empty() {
+        if !job.run() {
+            return;
+        }
+        let roots = enabled_roots(&config);
+        if roots.is_empty() {
             send_to_frontend(
                 &app_clone,
                 "No paths configured for listing".to_string(),
                 "status_update",
             );
+            job.complete();
             return;
         }
 
-        let mut directories = expand_paths(priority_paths);
-        directories.sort();
+        let rules = Arc::new(ExclusionRules::from_config(&config));
+        // Expanded up front so progress knows how many directories the whole scan covers
+        let scan_plan: Vec<_> = roots
+            .iter()
+            .map(|root| {
+                let expanded = expand_priority_paths(std::slice::from_ref(&root.path));
+                for warning in &expanded.warnings {
+                    send_to_frontend(&app_clone, warning.clone(), "status_update");
+                }
+                // Globs like `~/Documents/*` list every subdirectory, including ones like `node_modules`
+                let mut directories: Vec<PathBuf> = expanded
+                    .directories
+                    .iter()
+                    .filter(|directory| !rules.is_excluded(directory, true))
+                    .cloned()
+                    .collect();
+                if root.is_recursive() {
+                    directories = outermost_directories(directories);
+                }
+                directories.sort();
+                (root, expanded, directories)
+            })
+            .collect();
+        let progress = Arc::new(ScanProgress::new(
+            scan_plan.iter().map(|(_, _, directories)| directories.len()).sum(),
+        ));
+        let scan = ScanRun {
+            app: app_clone.clone(),
+            thumbnails: Batch::new(&app_clone, &job, Some(&progress)),
+            job,
+            progress,
+            sent_files: DashSet::new(),
+            stream_items,
+        };
+        scan.progress.emit(&app_clone, true);
+        // Every media file found by this scan; catalog entries outside it were removed from disk
+        let mut seen_files = HashSet::new();
 
-        for expanded_path in directories {
-            if CANCEL_FLAG.load(Ordering::SeqCst) {
+        for (root, expanded, directories) in scan_plan {
+            for expanded_path in directories {
+                if scan_cancelled(&scan) {
+                    return;
+                }
                 send_to_frontend(
                     &app_clone,
-                    "File listing operation cancelled".to_string(),
-                    "remove_all_data",
+                    format!("Fetching data from: {:?}", expanded_path),
+                    "status_update",
                 );
-                return;
-            }
-            send_to_frontend(
-                &app_clone,
-                format!("Fetching data from: {:?}", expanded_path),
-                "status_update",
-            );
 
-            if expanded_path.is_dir() {
-                let walkdir = WalkDir::new(&expanded_path).max_depth(
-                    if config["recursive"].as_bool().unwrap_or(false) {
-                        usize::MAX
-                    } else {
-                        1
-                    },
-                );
+                if expanded_path.is_dir() {
+                    let walker = ExclusionRules::walker(&rules, &expanded_path, root);
 
-                let mut file_paths: Vec<_> = walkdir
-                    .into_iter()
-                    .filter_map(|e| {
-                        if CANCEL_FLAG.load(Ordering::SeqCst) {
-                            return None;
-                        } // Check cancellation inside traversal
-                        e.ok()
-                    })
-                    .filter(|entry| entry.path().is_file())
-                    .map(|entry| entry.path().to_path_buf())
-                    .collect();
+                    let mut media_files: Vec<_> = walker
+                        .filter_map(|e| {
+                            if !scan.job.checkpoint() {
+                                return None;
+                            } // Check cancellation (and wait out pauses) inside traversal
+                            e.map_err(|_| scan.progress.walk_error()).ok()
+                        })
+                        .filter(|entry| entry.path().is_file())
+                        .flat_map(|entry| media_files(&scan, entry.path(), root))
+                        .collect();
 
-                if CANCEL_FLAG.load(Ordering::SeqCst) {
-                    send_to_frontend(
-                        &app_clone,
-                        "File listing operation cancelled".to_string(),
-                        "remove_all_data",
+                    if scan_cancelled(&scan) {
+                        return;
+                    } // Check after traversal
+                    media_files.sort_by(|a, b| a.path.cmp(&b.path));
+                    seen_files.extend(
+                        media_files
+                            .iter()
+                            .map(|found| found.path.to_string_lossy().into_owned()),
                     );
-                    return;
-                } // Check after traversal
-                file_paths.sort();
-                
-                const CHUNK_SIZE: usize = 10;
-                
-                // Process in chunks if there are enough files
-                if file_paths.len() >= CHUNK_SIZE {
-                    file_paths.par_chunks(CHUNK_SIZE).for_each(|chunk| {
-                        if CANCEL_FLAG.load(Ordering::SeqCst) { return; }
-                    
-                        let files: Vec<_> = chunk.par_iter().filter_map(|file_path| {
-                            let file_str = file_path.to_string_lossy().to_string();
-                            if CANCEL_FLAG.load(Ordering::SeqCst) || SENT_FILES.contains(&file_str) {
-                                return None;
-                            }
-                            
-                            let file_type = get_file_type(&file_path);
-                            if file_type != "unknown" {
-                                SENT_FILES.insert(file_str.clone());
-                                Some(json!({
-                                    "id": Uuid::new_v4().to_string(),
-                                    "file_path": file_path,
-                                    "path": crate::image_processor::process_thumbnail(file_path.to_str().unwrap()),
-                                    "type": file_type
-                                }))
-                            } else {
-                                None
-                            }
-                        }).collect();
-                    
-                        if !files.is_empty() && !CANCEL_FLAG.load(Ordering::SeqCst) {
-                            send_to_frontend(&app_clone, serde_json::to_string(&files).unwrap(), "file_path");
-                        }
-                    });
-                } else {
-                    // Handle case where there are fewer files than CHUNK_SIZE
-                    if !file_paths.is_empty() {
-                        let files: Vec<_> = file_paths.par_iter().filter_map(|file_path| {
-                            let file_str = file_path.to_string_lossy().to_string();
-                            if CANCEL_FLAG.load(Ordering::SeqCst) || SENT_FILES.contains(&file_str) {
-                                return None;
-                            }
-                            
-                            let file_type = get_file_type(&file_path);
-                            if file_type != "unknown" {
-                                SENT_FILES.insert(file_str.clone());
-                                Some(json!({
-                                    "id": Uuid::new_v4().to_string(),
-                                    "file_path": file_path,
-                                    "path": crate::image_processor::process_thumbnail(file_path.to_str().unwrap()),
-                                    "type": file_type
-                                }))
-                            } else {
-                                None
-                            }
-                        }).collect();
-                        
-                        if !files.is_empty() && !CANCEL_FLAG.load(Ordering::SeqCst) {
-                            send_to_frontend(&app_clone, serde_json::to_string(&files).unwrap(), "file_path");
-                        }
+                    scan.progress.files_discovered(media_files.len());
+
+                    emit_media_items(&scan, &media_files);
+                    if !stream_items {
+                        send_catalog_updated(&app_clone);
                     }
                 }
+                scan.progress.directory_done();
+                scan.progress.emit(&app_clone, true);
+
+                if scan_cancelled(&scan) {
+                    return;
+                } // Check after directory
             }
 
-            if CANCEL_FLAG.load(Ordering::SeqCst) {
-                send_to_frontend(
-                    &app_clone,
-                    "File listing operation cancelled".to_string(),
-                    "remove_all_data",
-                );
+            // Files matched directly by a pattern such as `~/Downloads/**/*.png`
+            let matched_files: Vec<_> = expanded
+                .files
+                .iter()
+                .filter(|file_path| !rules.is_excluded(file_path, false))
+                .flat_map(|file_path| media_files(&scan, file_path, root))
+                .collect();
+            seen_files.extend(matched_files.iter().map(|found| found.path.to_string_lossy().into_owned()));
+            scan.progress.files_discovered(matched_files.len());
+            emit_media_items(&scan, &matched_files);
+            if !stream_items {
+                send_catalog_updated(&app_clone);
+            }
+            if scan_cancelled(&scan) {
                 return;
-            } // Check after directory
+            }
         }
 
+        // Thumbnails of files in the viewport were generated first; the summary covers all
+        scan.thumbnails.wait();
+        if scan_cancelled(&scan) {
+            return;
+        }
+
+        match catalog::remove_missing(&seen_files) {
+            Ok(removed) if !removed.is_empty() => {
+                send_to_frontend(&app_clone, json!(removed).to_string(), "file_removed");
+            }
+            Ok(_) => {}
+            Err(e) => send_to_frontend(&app_clone, format!("Failed to update catalog: {}", e), "error"),
+        }
+        // The indexer cannot open archives; hand it the members through their staged thumbnails
+        archive::sync_indexer(
+            &app_clone,
+            seen_files
+                .iter()
+                .map(String::as_str)
+                .filter(|path| archive::is_member_path(Path::new(path))),
+        );
+        // Pick up files created after this pass without another full scan
+        watcher::start_watching(app_clone.clone(), roots, rules);
+        let sweep = thumbnail_cache::sweep(thumbnail_cache::budget_bytes(&config));
+        if sweep.bytes_freed > 0 {
+            println!("Thumbnail cache sweep: {:?}", sweep);
+        }
+        if let Err(e) = thumbnail_cache::save_index() {
+            send_to_frontend(&app_clone, format!("Failed to save thumbnail index: {}", e), "error");
+        }
+        scan.progress.finish(&app_clone, false);
+        scan.job.complete();
         send_to_frontend(
             &app_clone,
             "File listing completed".to_string(),
             "status_update",
         );
     });
+    job_id
+}
+
+/// The media at `file_path`: the file itself, or the image members of a ZIP/CBZ archive.
+fn media_files(scan: &ScanRun, file_path: &Path, root: &RootSettings) -> Vec<Found> {
+    if !archive::is_archive(file_path) {
+        return media_file(file_path, root).into_iter().collect();
+    }
+    if !root.allows_type("image") {
+        return Vec::new();
+    }
+    match archive::list_members(file_path) {
+        Ok(members) => members
+            .into_iter()
+            .map(|member| Found {
+                path: member.path,
+                media: member.media,
+                metadata: Some((member.size, member.mtime)),
+            })
+            .collect(),
+        Err(e) => {
+            println!("Failed to list archive {:?}: {}", file_path, e);
+            scan.progress.walk_error();
+            Vec::new()
+        }
+    }
+}
+
+/// The detected type of a media file the root's `media_types` allow.
+fn media_file(file_path: &Path, root: &RootSettings) -> Option<Found> {
+    get_file_type(file_path)
+        .filter(|media| root.allows_type(media.category()))
+        .map(|media| Found {
+            path: file_path.to_path_buf(),
+            media,
+            metadata: None,
+        })
+}
+
+/// Reports the cancellation (and the scan summary so far) if the scan was cancelled. Blocks
+/// while the scan is paused.
+fn scan_cancelled(scan: &ScanRun) -> bool {
+    if scan.job.checkpoint() {
+        return false;
+    }
+    scan.progress.finish(&scan.app, true);
+    send_to_frontend(
+        &scan.app,
+        "File listing operation cancelled".to_string(),
+        "remove_all_data",
+    );
+    true
+}
+
+/// Catalogs media files in parallel chunks, queuing their thumbnails, and emits each chunk as
+/// a `file_path` event. A paused scan waits on its own thread between batches of chunks, never
+/// inside the rayon pool that other scans and commands share.
+fn emit_media_items(scan: &ScanRun, file_paths: &[Found]) {
+    const CHUNK_SIZE: usize = 10;
+    let app = &scan.app;
+    let batch_size = CHUNK_SIZE * rayon::current_num_threads().max(1);
+
+    for batch in file_paths.chunks(batch_size) {
+        if !scan.job.checkpoint() {
+            return;
+        }
+        batch.par_chunks(CHUNK_SIZE).for_each(|chunk| {
+            if scan.job.is_cancelled() {
+                return;
+            }
+
+            let files: Vec<_> = chunk
+                .par_iter()
+                .filter_map(|found| scan_item(scan, found))
+                .collect();
+
+            if scan.stream_items && !files.is_empty() && !scan.job.is_cancelled() {
+                send_to_frontend(app, serde_json::to_string(&files).unwrap(), "file_path");
+            }
+            scan.progress.emit(app, false);
+        });
+    }
+}
+
+fn send_catalog_updated(app: &AppHandle) {
+    if let Ok(total) = catalog::count() {
+        send_to_frontend(app, json!({ "total": total }).to_string(), "catalog_updated");
+    }
+}
+
+/// Builds the media item emitted to the frontend. Files unchanged since the last scan are
+/// served from the catalog; files that cannot be thumbnailed get a placeholder tile and a
+/// `thumbnail_error` event instead of aborting the scan.
+pub fn build_media_item(app: &AppHandle, file_path: &Path, media: &MediaType) -> Value {
+    match scan_entry(file_path, media, None) {
+        Scanned::Current(entry) => {
+            thumbnail_cache::touch(entry.thumbnail_key.as_deref());
+            entry.to_media_item()
+        }
+        Scanned::Changed(mut entry) => {
+            thumbnail_entry(app, &mut entry, media.kind);
+            entry.to_media_item()
+        }
+    }
+}
+
+/// The item a scan emits for a file. Changed files are catalogued right away with a pending
+/// thumbnail, which is queued (see `thumbnail_queue`) and announced by `thumbnail_ready`; until
+/// the frontend reports a viewport they are thumbnailed inline instead.
+fn scan_item(scan: &ScanRun, found: &Found) -> Option<Value> {
+    let file_str = found.path.to_string_lossy().to_string();
+    if scan.job.is_cancelled() || scan.sent_files.contains(&file_str) {
+        return None;
+    }
+    scan.sent_files.insert(file_str);
+
+    match scan_entry(&found.path, &found.media, found.metadata) {
+        Scanned::Current(entry) => {
+            scan.progress.record(ItemOutcome::Cached);
+            thumbnail_cache::touch(entry.thumbnail_key.as_deref());
+            Some(entry.to_media_item())
+        }
+        Scanned::Changed(mut entry) if !thumbnail_queue::viewport_reported() => {
+            scan.progress.record(thumbnail_entry(&scan.app, &mut entry, found.media.kind));
+            Some(entry.to_media_item())
+        }
+        Scanned::Changed(entry) => {
+            if let Err(e) = catalog::upsert(&entry) {
+                println!("Failed to update catalog for {}: {}", entry.path, e);
+            }
+            thumbnail_queue::enqueue(&scan.app, entry.path.clone(), found.media.kind, Some(Arc::clone(&scan.thumbnails)));
+            Some(entry.to_media_item())
+        }
+    }
+}
+
+/// A file as found by a scan.
+enum Scanned {
+    /// Unchanged since it was catalogued, thumbnail included
+    Current(CatalogEntry),
+    /// New or changed, with its id but no thumbnail yet
+    Changed(CatalogEntry),
+}
+
+/// `metadata` is the size and mtime when the caller already has them, as archive listings do.
+fn scan_entry(file_path: &Path, media: &MediaType, metadata: Option<(u64, u64)>) -> Scanned {
+    let file_str = file_path.to_string_lossy().into_owned();
+    // Archive members have no inode of their own to follow across moves
+    let (size, mtime, identity) = if let Some((size, mtime)) = metadata {
+        (size, mtime, None)
+    } else if archive::is_member_path(file_path) {
+        let (size, mtime) = archive::member_metadata(file_path).unwrap_or_default();
+        (size, mtime, None)
+    } else {
+        let metadata = fs::metadata(file_path).ok();
+        let (size, mtime) = metadata
+            .as_ref()
+            .map(|metadata| (metadata.len(), modified_nanos(metadata)))
+            .unwrap_or_default();
+        (size, mtime, metadata.as_ref().and_then(file_identity))
+    };
+
+    let id = match catalog::get(&file_str) {
+        Some(entry) if entry.is_current(size, mtime) && !entry.id.is_empty() => {
+            return Scanned::Current(entry);
+        }
+        Some(entry) if !entry.id.is_empty() => entry.id,
+        // A file that moved keeps the id it had at its old path
+        _ => identity
+            .and_then(|(dev, inode)| catalog::take_moved(dev, inode))
+            .map(|moved| moved.id)
+            .filter(|id| !id.is_empty())
+            .unwrap_or_else(|| media_id(file_path)),
+    };
+
+    Scanned::Changed(CatalogEntry {
+        id,
+        path: file_str,
+        size,
+        mtime,
+        media_type: media.category().to_string(),
+        mime_type: Some(media.mime_type().to_string()),
+        extension_mismatch: media.extension_mismatch,
+        width: None,
+        height: None,
+        orientation: None,
+        frame_count: None,
+        duration_ms: None,
+        placeholder: None,
+        phash: None,
+        content_hash: None,
+        thumbnail_key: None,
+        thumbnail_error: None,
+        thumbnail_version: None,
+        dev: identity.map(|(dev, _)| dev),
+        inode: identity.map(|(_, inode)| inode),
+    })
+}
+
+/// Thumbnails a catalogued file and saves the outcome to the catalog, sending a
+/// `thumbnail_error` event if it fails. Returns how the thumbnail was obtained.
+pub fn thumbnail_entry(app: &AppHandle, entry: &mut CatalogEntry, kind: MediaKind) -> ItemOutcome {
+    entry.thumbnail_version = Some(catalog::thumbnail_version());
+    let outcome = match process_thumbnail(&entry.path, kind) {
+        Ok(thumbnail) => {
+            entry.width = Some(thumbnail.width);
+            entry.height = Some(thumbnail.height);
+            entry.orientation = Some(thumbnail.orientation);
+            entry.frame_count = Some(thumbnail.animation.map_or(1, |animation| animation.frame_count));
+            entry.duration_ms = thumbnail.animation.map(|animation| animation.duration_ms);
+            entry.placeholder = thumbnail.placeholder;
+            entry.phash = thumbnail.phash;
+            entry.thumbnail_key = Some(thumbnail.key);
+            entry.thumbnail_error = None;
+            if thumbnail.cached {
+                ItemOutcome::Cached
+            } else {
+                ItemOutcome::Generated(entry.size)
+            }
+        }
+        Err(e) => {
+            send_to_frontend(
+                app,
+                json!({
+                    "id": entry.id,
+                    "file_path": entry.path,
+                    "reason": e.kind(),
+                    "message": e.to_string()
+                })
+                .to_string(),
+                "thumbnail_error",
+            );
+            entry.thumbnail_error = Some(e.kind().to_string());
+            ItemOutcome::Failed
+        }
+    };
+
+    if let Err(e) = catalog::upsert(entry) {
+        println!("Failed to update catalog for {}: {}", entry.path, e);
+    }
+    outcome
 }
 
+/// Cancels every running scan; single jobs are cancelled with `cancel_job`.
 #[tauri::command]
 pub async fn cancel_list_files(app: AppHandle) -> Result<(), String> {
-    CANCEL_FLAG.store(true, Ordering::SeqCst);
-    SENT_FILES.clear(); // Reset sent file tracking
+    jobs::cancel_kind("scan");
     send_to_frontend(
         &app,
         "Cancelling file listing operation...".to_string(),
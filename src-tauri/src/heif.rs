@@ -0,0 +1,279 @@
+use std::fs;
+
+/// A HEIF grid image: the primary item is the `tiles` (item ids, which ffmpeg uses as stream
+/// ids) laid out in `rows` x `columns` row-major and cropped to `width` x `height`.
+pub struct Grid {
+    pub rows: u32,
+    pub columns: u32,
+    pub width: u32,
+    pub height: u32,
+    pub tiles: Vec<u32>,
+    /// Anticlockwise quarter turns from the grid's `irot` property
+    pub rotation: u8,
+}
+
+/// The grid layout of `path`'s primary image; `None` for files whose primary image is not a grid.
+pub fn grid(path: &str) -> Option<Grid> {
+    parse_grid(&fs::read(path).ok()?)
+}
+
+fn parse_grid(data: &[u8]) -> Option<Grid> {
+    let meta = boxes(data).find(|(kind, _)| kind == b"meta")?.1.get(4..)?;
+    let child = |wanted: &[u8; 4]| boxes(meta).find(|(kind, _)| kind == wanted).map(|(_, body)| body);
+
+    let pitm = child(b"pitm")?;
+    let primary = if *pitm.first()? == 0 { Bytes(pitm, 4).u16()? as u32 } else { Bytes(pitm, 4).u32()? };
+    if item_type(child(b"iinf")?, primary)? != *b"grid" {
+        return None;
+    }
+
+    let iref = child(b"iref")?;
+    let short_ids = *iref.first()? == 0;
+    let tiles = boxes(iref.get(4..)?).find_map(|(kind, body)| {
+        let mut reader = Bytes(body, 0);
+        let from = reader.id(short_ids)?;
+        if kind != *b"dimg" || from != primary {
+            return None;
+        }
+        let count = reader.u16()?;
+        (0..count).map(|_| reader.id(short_ids)).collect::<Option<Vec<u32>>>()
+    })?;
+
+    let descriptor = item_data(data, child(b"iloc")?, child(b"idat"), primary)?;
+    let mut reader = Bytes(&descriptor, 1);
+    let wide = reader.u8()? & 1 == 1;
+    let rows = reader.u8()? as u32 + 1;
+    let columns = reader.u8()? as u32 + 1;
+    let (width, height) = if wide {
+        (reader.u32()?, reader.u32()?)
+    } else {
+        (reader.u16()? as u32, reader.u16()? as u32)
+    };
+    if tiles.len() != (rows * columns) as usize {
+        return None;
+    }
+
+    let rotation = child(b"iprp")
+        .and_then(|iprp| rotation(iprp, primary))
+        .unwrap_or(0);
+    Some(Grid {
+        rows,
+        columns,
+        width,
+        height,
+        tiles,
+        rotation,
+    })
+}
+
+/// The four-character type of item `id` from the `iinf` box.
+fn item_type(iinf: &[u8], id: u32) -> Option<[u8; 4]> {
+    let entries = iinf.get(if *iinf.first()? == 0 { 6 } else { 8 }..)?;
+    boxes(entries).find_map(|(kind, infe)| {
+        let version = *infe.first()?;
+        // Item types only exist from `infe` version 2 on
+        if kind != *b"infe" || version < 2 {
+            return None;
+        }
+        let mut reader = Bytes(infe, 4);
+        if reader.id(version == 2)? != id {
+            return None;
+        }
+        reader.u16()?;
+        reader.take(4)?.try_into().ok()
+    })
+}
+
+/// The bytes of item `id`, from the file (construction method 0) or the `idat` box (1).
+fn item_data(data: &[u8], iloc: &[u8], idat: Option<&[u8]>, id: u32) -> Option<Vec<u8>> {
+    let version = *iloc.first()?;
+    let mut reader = Bytes(iloc, 4);
+    let sizes = reader.u8()?;
+    let (offset_size, length_size) = (sizes >> 4, sizes & 0xF);
+    let sizes = reader.u8()?;
+    let base_offset_size = sizes >> 4;
+    let index_size = if version > 0 { sizes & 0xF } else { 0 };
+    let count = if version < 2 { reader.u16()? as u32 } else { reader.u32()? };
+
+    for _ in 0..count {
+        let item = reader.id(version < 2)?;
+        let method = if version > 0 { reader.u16()? & 0xF } else { 0 };
+        reader.u16()?;
+        let base = reader.uint(base_offset_size)?;
+        let mut bytes = Vec::new();
+        for _ in 0..reader.u16()? {
+            reader.uint(index_size)?;
+            let offset = usize::try_from(base.checked_add(reader.uint(offset_size)?)?).ok()?;
+            let length = usize::try_from(reader.uint(length_size)?).ok()?;
+            if item != id {
+                continue;
+            }
+            let source = if method == 1 { idat? } else { data };
+            bytes.extend_from_slice(source.get(offset..offset.checked_add(length)?)?);
+        }
+        if item == id {
+            return Some(bytes);
+        }
+    }
+    None
+}
+
+/// Quarter turns of the `irot` property associated with item `id` in the `iprp` box.
+fn rotation(iprp: &[u8], id: u32) -> Option<u8> {
+    let ipco = boxes(iprp).find(|(kind, _)| kind == b"ipco")?.1;
+    let ipma = boxes(iprp).find(|(kind, _)| kind == b"ipma")?.1;
+    let (version, wide_index) = (*ipma.first()?, ipma.get(3)? & 1 == 1);
+    let mut reader = Bytes(ipma, 4);
+    for _ in 0..reader.u32()? {
+        let item = reader.id(version < 1)?;
+        let associations: Vec<usize> = (0..reader.u8()?)
+            .map(|_| match wide_index {
+                true => reader.u16().map(|index| (index & 0x7FFF) as usize),
+                false => reader.u8().map(|index| (index & 0x7F) as usize),
+            })
+            .collect::<Option<_>>()?;
+        if item == id {
+            // Property indices are 1-based; 0 means none
+            return associations.into_iter().find_map(|index| {
+                let (kind, body) = boxes(ipco).nth(index.checked_sub(1)?)?;
+                if kind != *b"irot" {
+                    return None;
+                }
+                body.first().map(|angle| angle & 3)
+            });
+        }
+    }
+    None
+}
+
+/// The (type, body) of each ISO-BMFF box in `data`, stopping at the first malformed one.
+fn boxes(data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        let mut reader = Bytes(rest, 0);
+        let size = reader.u32()? as usize;
+        let kind: [u8; 4] = reader.take(4)?.try_into().ok()?;
+        let (header, size) = match size {
+            0 => (8, rest.len()),
+            1 => (16, usize::try_from(reader.uint(8)?).ok()?),
+            size => (8, size),
+        };
+        let body = rest.get(header..size)?;
+        rest = &rest[size..];
+        Some((kind, body))
+    })
+}
+
+/// Big-endian cursor over a box body.
+struct Bytes<'a>(&'a [u8], usize);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let bytes = self.0.get(self.1..self.1.checked_add(length)?)?;
+        self.1 += length;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    /// An unsigned field of 0, 4 or 8 bytes, as `iloc` sizes its fields.
+    fn uint(&mut self, size: u8) -> Option<u64> {
+        match size {
+            0 => Some(0),
+            4 => self.u32().map(u64::from),
+            8 => Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    /// An item id, 16 bits wide in the older box versions.
+    fn id(&mut self, short: bool) -> Option<u32> {
+        if short {
+            self.u16().map(u32::from)
+        } else {
+            self.u32()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn boxed(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    fn infe(id: u16, item_type: &[u8; 4]) -> Vec<u8> {
+        let mut body = vec![2, 0, 0, 0];
+        body.extend_from_slice(&id.to_be_bytes());
+        body.extend_from_slice(&[0, 0]);
+        body.extend_from_slice(item_type);
+        body.push(0);
+        boxed(b"infe", &body)
+    }
+
+    /// A 2x1 grid of items 2 and 3, 1000x500, whose descriptor sits in `idat`.
+    fn grid_file(properties: &[u8], associations: &[u8]) -> Vec<u8> {
+        let mut iinf = vec![0, 0, 0, 0, 0, 3];
+        for item in [infe(1, b"grid"), infe(2, b"hvc1"), infe(3, b"hvc1")] {
+            iinf.extend(item);
+        }
+        let iref = [&[0, 0, 0, 0][..], &boxed(b"dimg", &[0, 1, 0, 2, 0, 2, 0, 3])].concat();
+        // Version 1: 4-byte offsets and lengths, construction method 1 (idat)
+        let iloc = [0x01, 0, 0, 0, 0x44, 0x00, 0, 1, 0, 1, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 8];
+        let idat = [0, 0, 0, 1, 0x03, 0xE8, 0x01, 0xF4];
+        let ipma = [&[0, 0, 0, 0, 0, 0, 0, 1, 0, 1, associations.len() as u8][..], associations].concat();
+        let iprp = [boxed(b"ipco", properties), boxed(b"ipma", &ipma)].concat();
+
+        let meta = [
+            vec![0, 0, 0, 0],
+            boxed(b"pitm", &[0, 0, 0, 0, 0, 1]),
+            boxed(b"iinf", &iinf),
+            boxed(b"iref", &iref),
+            boxed(b"iloc", &iloc),
+            boxed(b"idat", &idat),
+            boxed(b"iprp", &iprp),
+        ]
+        .concat();
+        [boxed(b"ftyp", b"heic\0\0\0\0mif1heic"), boxed(b"meta", &meta)].concat()
+    }
+
+    #[test]
+    fn parses_grid_layout_and_rotation() {
+        let properties = [boxed(b"ispe", &[0; 12]), boxed(b"irot", &[1])].concat();
+        let grid = parse_grid(&grid_file(&properties, &[0x81, 0x82])).unwrap();
+        assert_eq!((grid.rows, grid.columns), (1, 2));
+        assert_eq!((grid.width, grid.height), (1000, 500));
+        assert_eq!(grid.tiles, vec![2, 3]);
+        assert_eq!(grid.rotation, 1);
+    }
+
+    #[test]
+    fn unrotated_without_irot() {
+        let grid = parse_grid(&grid_file(&boxed(b"ispe", &[0; 12]), &[0x81])).unwrap();
+        assert_eq!(grid.rotation, 0);
+    }
+
+    #[test]
+    fn ignores_images_without_a_grid() {
+        let mut file = grid_file(&[], &[]);
+        let at = file.windows(4).position(|window| window == b"grid").unwrap();
+        file[at..at + 4].copy_from_slice(b"hvc1");
+        assert!(parse_grid(&file).is_none());
+        assert!(parse_grid(b"\0\0\0\x08ftyp").is_none());
+    }
+}
@@ -1,71 +1,521 @@
+use crate::animation::{self, Animation};
+use crate::blurhash;
+use crate::duplicates;
+use crate::archive;
+use crate::color_profile;
+use crate::exif;
+use crate::fast_path;
 use crate::initialise::EnvPaths;
-use image::{imageops::FilterType, io::Reader as ImageReader, DynamicImage, GenericImageView};
-use std::{fs::File, io::Write, path::Path, path::PathBuf};
+use crate::media_kind::MediaKind;
+use crate::catalog;
+use crate::thumbnail_cache::{self, CacheEntry, SourceFingerprint, PYRAMID_LEVELS};
+use crate::thumbnail_profile::{self, ThumbnailProfile};
+use crate::raw_processor::extract_preview;
+use crate::utils::get_file_type;
+use crate::video_processor::{decode_still, extract_frame};
+use image::{io::Reader as ImageReader, DynamicImage, GenericImageView, ImageError, ImageFormat, Rgba, RgbaImage};
+use resvg::{tiny_skia, usvg};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::io::{Cursor, Write};
+use std::{fmt, fs, fs::File, path::Path, path::PathBuf};
 use webp::Encoder;
 
-pub fn process_thumbnail(image_path: &str) -> String {
-    // Load image
-    let img = ImageReader::open(image_path)
-        .expect("Failed to open image")
-        .decode()
-        .expect("Failed to decode image");
+// Longest edge SVGs are rasterized at before the regular thumbnail resize
+const SVG_RENDER_EDGE: f32 = 1024.0;
 
-    // Setup paths
-    let paths = EnvPaths::new();
-    let output_path = paths.thumbnail_path.join(
-        Path::new(image_path)
-            .file_stem()
-            .expect("Invalid filename")
-            .to_string_lossy()
-            .to_string()
-            + ".webp",
+lazy_static::lazy_static! {
+    // Loading system fonts is slow, so every SVG render shares one database
+    static ref SVG_FONTS: Arc<usvg::fontdb::Database> = {
+        let mut fonts = usvg::fontdb::Database::new();
+        fonts.load_system_fonts();
+        Arc::new(fonts)
+    };
+}
+
+#[derive(Debug)]
+pub enum ThumbnailError {
+    Unreadable(String),
+    UnsupportedFormat(String),
+    DecodeFailed(String),
+    WriteFailed(String),
+}
+
+impl ThumbnailError {
+    /// Stable identifier sent to the frontend alongside the message.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ThumbnailError::Unreadable(_) => "unreadable",
+            ThumbnailError::UnsupportedFormat(_) => "unsupported_format",
+            ThumbnailError::DecodeFailed(_) => "decode_failed",
+            ThumbnailError::WriteFailed(_) => "write_failed",
+        }
+    }
+}
+
+impl fmt::Display for ThumbnailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThumbnailError::Unreadable(e) => write!(f, "Failed to read file: {}", e),
+            ThumbnailError::UnsupportedFormat(e) => write!(f, "Unsupported format: {}", e),
+            ThumbnailError::DecodeFailed(e) => write!(f, "Failed to decode: {}", e),
+            ThumbnailError::WriteFailed(e) => write!(f, "Failed to write thumbnail: {}", e),
+        }
+    }
+}
+
+impl From<ImageError> for ThumbnailError {
+    fn from(error: ImageError) -> Self {
+        match error {
+            ImageError::Unsupported(e) => ThumbnailError::UnsupportedFormat(e.to_string()),
+            ImageError::IoError(e) => ThumbnailError::Unreadable(e.to_string()),
+            e => ThumbnailError::DecodeFailed(e.to_string()),
+        }
+    }
+}
+
+/// A generated (or cached) thumbnail together with what is known about its source.
+pub struct Thumbnail {
+    pub path: String,
+    pub key: String,
+    pub width: u32,
+    pub height: u32,
+    /// EXIF orientation (1-8) the thumbnail was turned upright by
+    pub orientation: u32,
+    /// Set for animated GIFs and WebPs, whose preview is at `thumbnail_cache::preview_path`
+    pub animation: Option<Animation>,
+    /// BlurHash the UI paints until the thumbnail loads; `None` if the thumbnail can't be read
+    pub placeholder: Option<String>,
+    /// dHash of the source, for `duplicates::find_duplicates`; `None` when the source was not
+    /// decoded, which computes it when needed
+    pub phash: Option<u64>,
+    /// Served from the thumbnail cache without decoding the source
+    pub cached: bool,
+}
+
+/// A decoded source: the upright image for the thumbnail pipeline (the first frame of an
+/// animation), the source's own size (as displayed, i.e. after orientation) and the EXIF
+/// orientation that was applied.
+pub struct Decoded {
+    pub img: DynamicImage,
+    pub dimensions: (u32, u32),
+    pub orientation: u32,
+    pub animation: Option<Animation>,
+}
+
+impl Decoded {
+    /// Turns `img` upright; the reported size is taken after rotation.
+    pub fn upright(img: DynamicImage, orientation: u32) -> Self {
+        let img = apply_orientation(img, orientation);
+        let dimensions = img.dimensions();
+        Self {
+            img,
+            dimensions,
+            orientation,
+            animation: None,
+        }
+    }
+}
+
+// Sources that are already upright, such as video frames
+impl From<(DynamicImage, (u32, u32))> for Decoded {
+    fn from((img, dimensions): (DynamicImage, (u32, u32))) -> Self {
+        Self {
+            img,
+            dimensions,
+            orientation: 1,
+            animation: None,
+        }
+    }
+}
+
+pub fn process_thumbnail(image_path: &str, kind: MediaKind) -> Result<Thumbnail, ThumbnailError> {
+    let fingerprint = SourceFingerprint::of(Path::new(image_path))
+        .map_err(|e| ThumbnailError::Unreadable(e.to_string()))?;
+    if let Some(entry) = thumbnail_cache::lookup(&fingerprint).filter(|entry| animation_cached(entry, kind)) {
+        let path = thumbnail_cache::entry_path(&entry.key);
+        // Entries from before placeholders get one from their thumbnail, without a decode of
+        // the source
+        let placeholder = entry.placeholder.clone().or_else(|| {
+            let placeholder = thumbnail_placeholder(&path)?;
+            thumbnail_cache::set_placeholder(&fingerprint, &entry.key, &placeholder);
+            Some(placeholder)
+        });
+        return Ok(Thumbnail {
+            path: path.to_string_lossy().into_owned(),
+            animation: entry.animation(),
+            placeholder,
+            phash: entry.phash,
+            key: entry.key,
+            width: entry.width,
+            height: entry.height,
+            orientation: entry.orientation.unwrap_or(1),
+            cached: true,
+        });
+    }
+
+    let key = fingerprint.cache_key();
+    let output_path = thumbnail_cache::entry_path(&key);
+
+    let profile = thumbnail_profile::current();
+    // A thumbnail already on disk (e.g. after the index was lost) only needs the source's header
+    let probed = if output_path.exists() { probe_source(image_path, kind) } else { None };
+    let (dimensions, orientation, animation, placeholder, phash) = match probed {
+        Some((dimensions, orientation, animation)) => (dimensions, orientation, animation, None, None),
+        None => {
+            let decoded = decode_source(image_path, kind, &profile)?;
+            let phash = duplicates::dhash(&decoded.img);
+            let placeholder = match output_path.exists() {
+                false => Some(write_thumbnail(&profile, decoded.img, &output_path)?),
+                true => None,
+            };
+            (decoded.dimensions, decoded.orientation, decoded.animation, placeholder, Some(phash))
+        }
+    };
+    // An animation whose preview fails is shown as a still rather than retried on every scan
+    let animation = animation.filter(|&animation| write_preview(image_path, animation, &profile, &key));
+    let placeholder = placeholder.or_else(|| thumbnail_placeholder(&output_path));
+    thumbnail_cache::record(
+        &fingerprint,
+        &key,
+        dimensions,
+        orientation,
+        animation,
+        placeholder.clone(),
+        phash,
     );
 
-    if !output_path.exists() {
-        // Calculate new dimensions
-        let max_width = 512;
-        let (width, height) = img.dimensions();
-        let (new_width, new_height) = if width > max_width {
-            (max_width, (height * max_width) / width)
-        } else {
-            (width, height)
-        };
-
-        // Resize with faster filter
-        let resized_img = img.resize(new_width, new_height, FilterType::Triangle);
-
-        // Convert to RGBA buffer for direct processing
-        let mut rgba_buffer = resized_img.to_rgba8();
-
-        // Combined contrast (15%) and brightness (+10) lookup table
-        let mut lookup_table = [0u8; 256];
-        for i in 0..256 {
-            let contrast_factor = 1.15; // 15% contrast increase
-            let brighten_value = 10;
-
-            let contrast_adjusted = ((i as f32 / 255.0 - 0.5) * contrast_factor + 0.5);
-            let brightened = (contrast_adjusted * 255.0).clamp(0.0, 255.0) as i32 + brighten_value;
-            lookup_table[i] = brightened.clamp(0, 255) as u8;
+    Ok(Thumbnail {
+        path: output_path.to_string_lossy().into_owned(),
+        key,
+        width: dimensions.0,
+        height: dimensions.1,
+        orientation,
+        animation,
+        placeholder,
+        phash,
+        cached: false,
+    })
+}
+
+/// What `decode_source` reports besides the pixels (displayed size, EXIF orientation and
+/// animation), read from the header for the formats `decode_image` handles. `None` for other
+/// kinds and unreadable headers, which need a decode.
+fn probe_source(image_path: &str, kind: MediaKind) -> Option<((u32, u32), u32, Option<Animation>)> {
+    match kind {
+        MediaKind::Svg | MediaKind::Heic | MediaKind::Avif => return None,
+        kind if kind.is_video() || kind.is_raw() => return None,
+        _ => {}
+    }
+
+    let data = read_source(image_path).ok()?;
+    let size = image_reader(&data, kind).ok()?.into_dimensions().ok()?;
+    let orientation = exif::orientation(&data).unwrap_or(1);
+    Some((oriented(size, orientation), orientation, animation::detect(&data)))
+}
+
+/// BlurHash placeholder of a thumbnail already on disk. Placeholders are computed from the
+/// thumbnail rather than the source so they match what the grid shows, profile adjustments
+/// included.
+fn thumbnail_placeholder(thumbnail_path: &Path) -> Option<String> {
+    match image::open(thumbnail_path) {
+        Ok(img) => Some(blurhash::encode(&img)),
+        Err(e) => {
+            println!("Failed to hash thumbnail {}: {}", thumbnail_path.display(), e);
+            None
+        }
+    }
+}
+
+/// Entries from before animation detection don't know whether a GIF or WebP moves, and
+/// animations need their preview on disk.
+fn animation_cached(entry: &CacheEntry, kind: MediaKind) -> bool {
+    match entry.frame_count {
+        Some(frame_count) => frame_count <= 1 || thumbnail_cache::preview_path(&entry.key).exists(),
+        None => !kind.may_be_animated(),
+    }
+}
+
+/// Writes the animated preview for the cache entry `key` unless it exists. Returns whether the
+/// preview is on disk.
+fn write_preview(image_path: &str, animation: Animation, profile: &ThumbnailProfile, key: &str) -> bool {
+    let preview_path = thumbnail_cache::preview_path(key);
+    if preview_path.exists() {
+        return true;
+    }
+
+    // Frames are decoded from the source again; the poster only needed the first
+    let result = read_source(image_path)
+        .map_err(|e| e.to_string())
+        .and_then(|data| animation::render_preview(&data, animation, profile))
+        .and_then(|data| write_cache_file(&data, &preview_path).map_err(|e| e.to_string()));
+    if let Err(e) = &result {
+        println!("Failed to generate animated preview for {}: {}", image_path, e);
+    }
+    result.is_ok()
+}
+
+fn read_source(image_path: &str) -> std::io::Result<Vec<u8>> {
+    if archive::is_member_path(Path::new(image_path)) {
+        archive::read_member(Path::new(image_path))
+    } else {
+        fs::read(image_path)
+    }
+}
+
+/// The smallest pyramid level covering `display_size` (the long edge in device pixels) and its
+/// path, generated on first use. Levels past the source's own size are never requested.
+pub fn thumbnail_for_size(
+    image_path: &str,
+    kind: MediaKind,
+    display_size: u32,
+) -> Result<(PathBuf, u32), ThumbnailError> {
+    let thumbnail = process_thumbnail(image_path, kind)?;
+    let source_edge = thumbnail.width.max(thumbnail.height).max(1);
+    let wanted = display_size.min(source_edge);
+    let level = PYRAMID_LEVELS
+        .iter()
+        .copied()
+        .find(|&level| level >= wanted)
+        .unwrap_or(PYRAMID_LEVELS[PYRAMID_LEVELS.len() - 1]);
+
+    let level_path = thumbnail_cache::level_path(&thumbnail.key, level);
+    if level_path.exists() {
+        return Ok((level_path, level));
+    }
+
+    let profile = thumbnail_profile::current().for_level(level);
+    // Levels the base thumbnail covers are scaled down from it rather than decoding the source
+    // again; its adjustments are already applied
+    let base = image::open(&thumbnail.path)
+        .ok()
+        .filter(|base| base.width().max(base.height()) >= level.min(source_edge));
+    let data = match base {
+        Some(base) => {
+            let mut profile = profile;
+            profile.adjustments.clear();
+            profile.render(base)
+        }
+        None => profile.render(decode_source(image_path, kind, &profile)?.img),
+    }
+    .map_err(ThumbnailError::WriteFailed)?;
+    write_cache_file(&data, &level_path)?;
+
+    Ok((level_path, level))
+}
+
+/// Thumbnail for showing `file_path` at `display_size` device pixels on its long edge, so dense
+/// grid zoom levels load small files and the preview pane a sharp one. Returns its `path` and
+/// pyramid `size`.
+#[tauri::command]
+pub async fn get_thumbnail_for_size(file_path: String, display_size: u32) -> Result<Value, String> {
+    // The catalog knows the sniffed type; files not scanned yet are detected here
+    let kind = catalog::get(&file_path)
+        .and_then(|entry| entry.mime_type)
+        .and_then(|mime_type| MediaKind::from_mime_type(&mime_type))
+        .or_else(|| get_file_type(Path::new(&file_path)).map(|media| media.kind))
+        .ok_or_else(|| format!("{} is not a supported media file", file_path))?;
+
+    let (path, size) = tokio::task::spawn_blocking(move || thumbnail_for_size(&file_path, kind, display_size))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())?;
+    Ok(json!({ "path": path, "size": size }))
+}
+
+/// dHash of a source whose thumbnail was made without decoding it, e.g. a cache hit.
+pub fn source_phash(image_path: &str, kind: MediaKind) -> Result<u64, String> {
+    let decoded = decode_source(image_path, kind, &thumbnail_profile::current()).map_err(|e| e.to_string())?;
+    Ok(duplicates::dhash(&decoded.img))
+}
+
+/// Decodes the source into an upright image for the thumbnail pipeline. `profile` is the one
+/// it will be rendered with; JPEGs are decoded no larger than it needs.
+fn decode_source(image_path: &str, kind: MediaKind, profile: &ThumbnailProfile) -> Result<Decoded, ThumbnailError> {
+    if archive::is_member_path(Path::new(image_path)) {
+        return decode_member(image_path, kind, profile);
+    }
+
+    match kind {
+        kind if kind.is_video() => extract_frame(image_path)
+            .map(Decoded::from)
+            .map_err(ThumbnailError::DecodeFailed),
+        MediaKind::Svg => {
+            let data = fs::read(image_path).map_err(|e| ThumbnailError::Unreadable(e.to_string()))?;
+            // Relative `<image href>`s resolve next to the file
+            render_svg(&data, Path::new(image_path).parent())
+        }
+        // `image` has no HEIF or AVIF decoder; the bundled ffmpeg has both
+        // ffmpeg applies HEIF rotation itself
+        MediaKind::Heic | MediaKind::Avif => decode_still(image_path)
+            .map(Decoded::from)
+            .map_err(ThumbnailError::DecodeFailed),
+        kind if kind.is_raw() => extract_preview(image_path).map_err(ThumbnailError::DecodeFailed),
+        _ => {
+            let data = fs::read(image_path).map_err(|e| ThumbnailError::Unreadable(e.to_string()))?;
+            decode_image(&data, kind, profile)
         }
+    }
+}
+
+/// Decodes an archive member from memory; `archive::list_members` only lists kinds that can be.
+fn decode_member(member_path: &str, kind: MediaKind, profile: &ThumbnailProfile) -> Result<Decoded, ThumbnailError> {
+    let data = archive::read_member(Path::new(member_path))
+        .map_err(|e| ThumbnailError::Unreadable(e.to_string()))?;
+    match kind {
+        MediaKind::Svg => render_svg(&data, None),
+        _ => decode_image(&data, kind, profile),
+    }
+}
+
+/// Reader for a still of the sniffed `kind`, so misnamed files decode without sniffing them again.
+fn image_reader(data: &[u8], kind: MediaKind) -> std::io::Result<ImageReader<Cursor<&[u8]>>> {
+    match ImageFormat::from_mime_type(kind.mime_type()) {
+        Some(format) => Ok(ImageReader::with_format(Cursor::new(data), format)),
+        None => ImageReader::new(Cursor::new(data)).with_guessed_format(),
+    }
+}
 
-        // Apply lookup table directly to pixel buffer
-        for pixel in rgba_buffer.chunks_exact_mut(4) {
-            pixel[0] = lookup_table[pixel[0] as usize];
-            pixel[1] = lookup_table[pixel[1] as usize];
-            pixel[2] = lookup_table[pixel[2] as usize];
-            // Alpha channel (pixel[3]) remains unchanged
+/// Decodes an in-memory still of the detected `kind`. Embedded ICC profiles are converted to
+/// sRGB and the EXIF orientation is applied before the resize. JPEGs well over `profile`'s
+/// output size are decoded at 1/2, 1/4 or 1/8 scale.
+pub fn decode_image(data: &[u8], kind: MediaKind, profile: &ThumbnailProfile) -> Result<Decoded, ThumbnailError> {
+    let orientation = exif::orientation(data).unwrap_or(1);
+    let scaled = match kind {
+        // The minimum size is in the JPEG's stored orientation
+        MediaKind::Jpeg => fast_path::decode_jpeg_scaled(data, |size| {
+            oriented(profile.output_size(oriented(size, orientation)), orientation)
+        }),
+        _ => None,
+    };
+    let (img, full_size) = match scaled {
+        Some((img, full_size)) => (img, Some(full_size)),
+        None => {
+            let img = image_reader(data, kind)
+                .map_err(|e| ThumbnailError::Unreadable(e.to_string()))?
+                .decode()?;
+            (img, None)
         }
+    };
+
+    let img = match color_profile::embedded_profile(data) {
+        Some(icc) => color_profile::to_srgb(img, &icc),
+        None => img,
+    };
+    let mut decoded = Decoded::upright(img, orientation);
+    decoded.animation = animation::detect(data);
+    // A scaled decode still reports the source's own size
+    if let Some(full_size) = full_size {
+        decoded.dimensions = oriented(full_size, orientation);
+    }
+    Ok(decoded)
+}
+
+/// `size` after applying an EXIF orientation; 5-8 swap the axes. Its own inverse.
+fn oriented((width, height): (u32, u32), orientation: u32) -> (u32, u32) {
+    match orientation {
+        5..=8 => (height, width),
+        _ => (width, height),
+    }
+}
+
+/// Rasterizes an SVG (or gzipped SVGZ) so its long edge is `SVG_RENDER_EDGE`; the reported
+/// dimensions are the document's own.
+fn render_svg(data: &[u8], resources_dir: Option<&Path>) -> Result<Decoded, ThumbnailError> {
+    let options = usvg::Options {
+        resources_dir: resources_dir.map(Path::to_path_buf),
+        fontdb: Arc::clone(&SVG_FONTS),
+        ..usvg::Options::default()
+    };
+    let tree = usvg::Tree::from_data(data, &options)
+        .map_err(|e| ThumbnailError::DecodeFailed(e.to_string()))?;
+
+    let size = tree.size();
+    let scale = SVG_RENDER_EDGE / size.width().max(size.height());
+    let width = ((size.width() * scale).round() as u32).max(1);
+    let height = ((size.height() * scale).round() as u32).max(1);
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| ThumbnailError::DecodeFailed("SVG has an empty canvas".to_string()))?;
+    resvg::render(&tree, tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+    // tiny-skia keeps premultiplied alpha, `image` expects straight alpha
+    let pixels: Vec<u8> = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    let img = RgbaImage::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| ThumbnailError::DecodeFailed("SVG render has the wrong size".to_string()))?;
+
+    Ok(Decoded::from((img, (size.width().round() as u32, size.height().round() as u32))))
+}
+
+/// Rotates/flips `img` upright according to an EXIF orientation value (1-8).
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
 
-        // Create WebP encoder from raw buffer
-        let (width, height) = rgba_buffer.dimensions();
-        let encoder = Encoder::from_rgba(rgba_buffer.as_ref(), width, height);
+/// Tile shown in place of files whose thumbnail could not be generated, tinted by the
+/// file's format so e.g. an undecodable HEIC is still recognisable as a photo.
+pub fn placeholder_thumbnail(mime_type: Option<&str>) -> String {
+    let kind = mime_type.and_then(MediaKind::from_mime_type);
+    let file_name = match kind {
+        // e.g. `placeholder-image-svg-xml.webp`
+        Some(kind) => format!("placeholder-{}.webp", kind.mime_type().replace(['/', '+'], "-")),
+        None => "placeholder.webp".to_string(),
+    };
+    let paths = EnvPaths::new();
+    let output_path = paths.thumbnail_path.join(file_name);
 
-        // Encode and save
-        let webp_data = encoder.encode(75.0).to_vec();
-        File::create(&output_path)
-            .and_then(|mut f| f.write_all(&webp_data))
-            .expect("Failed to write thumbnail");
+    if !output_path.exists() {
+        let rgba_buffer = RgbaImage::from_pixel(64, 64, placeholder_color(kind));
+        let webp_data = Encoder::from_rgba(rgba_buffer.as_ref(), 64, 64).encode(75.0).to_vec();
+        if let Err(e) = fs::write(&output_path, webp_data) {
+            println!("Failed to write placeholder thumbnail: {}", e);
+        }
     }
 
     output_path.to_string_lossy().into_owned()
 }
+
+fn placeholder_color(kind: Option<MediaKind>) -> Rgba<u8> {
+    match kind {
+        Some(kind) if kind.is_video() => Rgba([32, 40, 72, 255]),
+        Some(MediaKind::Heic | MediaKind::Avif) => Rgba([64, 40, 72, 255]),
+        Some(MediaKind::Svg) => Rgba([24, 64, 64, 255]),
+        Some(MediaKind::Tiff | MediaKind::Bmp) => Rgba([72, 56, 32, 255]),
+        Some(kind) if kind.is_raw() => Rgba([72, 40, 32, 255]),
+        _ => Rgba([48, 48, 48, 255]),
+    }
+}
+
+/// Renders a decoded source with `profile`, writes it to the cache and returns its BlurHash.
+fn write_thumbnail(profile: &ThumbnailProfile, img: DynamicImage, output_path: &Path) -> Result<String, ThumbnailError> {
+    let frame = DynamicImage::ImageRgba8(profile.render_frame(img));
+    let placeholder = blurhash::encode(&frame);
+    let data = profile.encode(frame.into_rgba8()).map_err(ThumbnailError::WriteFailed)?;
+    write_cache_file(&data, output_path)?;
+    Ok(placeholder)
+}
+
+fn write_cache_file(data: &[u8], output_path: &Path) -> Result<(), ThumbnailError> {
+    if let Some(shard_dir) = output_path.parent() {
+        fs::create_dir_all(shard_dir).map_err(|e| ThumbnailError::WriteFailed(e.to_string()))?;
+    }
+    File::create(output_path)
+        .and_then(|mut f| f.write_all(data))
+        .map_err(|e| ThumbnailError::WriteFailed(e.to_string()))
+}
@@ -6,22 +6,32 @@ use std::path::PathBuf;
 use tauri::{AppHandle, Manager};
 use serde_json::Value;
 
-use crate::utils::{send_to_frontend, execute_command};
+use crate::scan_settings::PriorityPathConfig;
+use crate::utils::{send_to_frontend, execute_command, expand_priority_paths};
 use crate::search_processor::{start_python_process, index_data};
+use crate::thumbnail_profile;
+use crate::video_processor::set_ffmpeg_path;
 
 
 const APP_TEMP_DIR: &str = "snaphound";
 const VENV_DIR: &str = "venv";
 const CONFIG_FILE: &str = "config.json";
 const THUMBNAIL_DIR: &str = "thumbnail";
+const ARCHIVE_INDEX_DIR: &str = "archive_index";
 const SEARCH_PY: &str = "search.py";
+// Same directory Tauri resolves as the app data dir for our bundle identifier
+const APP_DATA_DIR: &str = "com.snaphound.app";
+const CATALOG_FILE: &str = "catalog.db";
 
 pub struct EnvPaths {
     pub python_binary: PathBuf,
     config_path: PathBuf,
     pub temp_dir: PathBuf,
     pub thumbnail_path: PathBuf,
+    /// Archive members staged as plain files for the indexer
+    pub archive_index_path: PathBuf,
     pub search_path: PathBuf,
+    pub catalog_path: PathBuf,
 }
 
 impl EnvPaths {
@@ -39,13 +49,22 @@ impl EnvPaths {
         let search_path = temp_dir.join(SEARCH_PY);
         let thumbnail_path = temp_dir.join(THUMBNAIL_DIR);
         fs::create_dir_all(&thumbnail_path).expect("Failed to create thumbnail directory");
+        let archive_index_path = temp_dir.join(ARCHIVE_INDEX_DIR);
+        fs::create_dir_all(&archive_index_path).expect("Failed to create archive index directory");
+
+        // Unlike the temp dir, the catalog has to survive reboots
+        let data_dir = dirs::data_dir().unwrap_or_else(|| temp_dir.clone()).join(APP_DATA_DIR);
+        fs::create_dir_all(&data_dir).expect("Failed to create application data directory");
+        let catalog_path = data_dir.join(CATALOG_FILE);
 
         Self {
             python_binary,
             config_path,
             temp_dir,
             thumbnail_path,
-            search_path
+            archive_index_path,
+            search_path,
+            catalog_path
         }
     }
 }
@@ -55,6 +74,7 @@ fn get_resource_path(app: &AppHandle, resource_type: &str) -> PathBuf {
         "venv" => "bin/dependency/venv",
         "config" => "bin/dependency/config.json",
         "search" => "bin/dependency/search.py",
+        "ffmpeg" => if cfg!(target_os = "windows") { "bin/dependency/ffmpeg.exe" } else { "bin/dependency/ffmpeg" },
         _ => {
             send_to_frontend(app, format!("Unsupported resource type: {}", resource_type), "error");
             return PathBuf::new();
@@ -207,6 +227,12 @@ pub async fn initialize_environment(app: AppHandle) {
         send_to_frontend(&app, format!("Failed to setup config: {}", e), "error");
         return;
     }
+
+    // Videos are thumbnailed through the bundled ffmpeg
+    set_ffmpeg_path(get_resource_path(&app, "ffmpeg"));
+    if let Ok(config) = fetch_config().await {
+        thumbnail_profile::load(&app, &config);
+    }
     start_python_process(app.clone()).await;
 
     // Only send this if all previous steps succeeded
@@ -222,18 +248,31 @@ pub async fn fetch_config() -> Result<Value, String> {
         .map_err(|e| e.to_string())
 }
 
+/// Saves the priority paths, each either a plain pattern or an object with its own scan
+/// settings (see `scan_settings::RootSettings`). Other config keys are left untouched.
 #[tauri::command]
-pub async fn save_config(priorityPath: Vec<String>, app: AppHandle) -> Result<(), String> {
-    let paths = EnvPaths::new();
-
-    // Convert the Vec<String> into JSON
-    let json_data = serde_json::json!({ "priority_paths": priorityPath });
+pub async fn save_config(priorityPath: Vec<PriorityPathConfig>, app: AppHandle) -> Result<Vec<String>, String> {
+    // Patterns that expand to nothing are still saved (the folder may appear later), but flagged
+    let patterns: Vec<String> = priorityPath.iter().map(|entry| entry.path().to_string()).collect();
+    let warnings = expand_priority_paths(&patterns).warnings;
+    for warning in &warnings {
+        send_to_frontend(&app, warning.clone(), "config_warning");
+    }
 
-    // Convert JSON to a string
-    let json_string = serde_json::to_string_pretty(&json_data).map_err(|e| e.to_string())?;
+    // Keep `recursive`, `exclude` and anything else already in the file
+    let mut json_data = fetch_config()
+        .await
+        .ok()
+        .filter(Value::is_object)
+        .unwrap_or_else(|| serde_json::json!({}));
+    json_data["priority_paths"] = serde_json::to_value(&priorityPath).map_err(|e| e.to_string())?;
+    write_config(&json_data)?;
 
-    // Write to config file
-    fs::write(&paths.config_path, json_string).map_err(|e| e.to_string())?;
+    Ok(warnings)
+}
 
-    Ok(())
-}
\ No newline at end of file
+pub fn write_config(config: &Value) -> Result<(), String> {
+    let paths = EnvPaths::new();
+    let json_string = serde_json::to_string_pretty(config).map_err(|e| e.to_string())?;
+    fs::write(&paths.config_path, json_string).map_err(|e| e.to_string())
+}
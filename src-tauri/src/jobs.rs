@@ -0,0 +1,215 @@
+use crate::utils::send_to_frontend;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::AppHandle;
+use uuid::Uuid;
+
+// Finished jobs kept around for `list_jobs`
+const MAX_FINISHED_JOBS: usize = 50;
+
+lazy_static::lazy_static! {
+    static ref JOBS: Mutex<Vec<Arc<Job>>> = Mutex::new(Vec::new());
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Cancelled,
+    Done,
+}
+
+impl JobState {
+    fn is_finished(self) -> bool {
+        matches!(self, JobState::Cancelled | JobState::Done)
+    }
+}
+
+/// A background job (scan, thumbnail warm-up, indexing, export, duplicate search) with its own cancellation
+/// and pause state. Workers call `checkpoint` between units of work; every state change is
+/// sent as a `job_updated` event.
+pub struct Job {
+    pub id: String,
+    /// `scan`, `thumbnail_warmup`, `indexing`, `export` or `duplicates`
+    pub kind: &'static str,
+    pub label: String,
+    /// Whether the job honors pause/cancel; the indexer runs out of process and does not
+    controllable: bool,
+    created_at: u64,
+    state: Mutex<JobState>,
+    resumed: Condvar,
+    app: AppHandle,
+}
+
+impl Job {
+    pub fn state(&self) -> JobState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state() == JobState::Cancelled
+    }
+
+    /// Blocks while the job is paused. Returns false once the job has been cancelled.
+    pub fn checkpoint(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while *state == JobState::Paused {
+            state = self.resumed.wait(state).unwrap();
+        }
+        *state != JobState::Cancelled
+    }
+
+    /// Moves a queued job to running. Returns false if it was cancelled before it started.
+    pub fn run(&self) -> bool {
+        self.transition(|state| (state == JobState::Queued).then_some(JobState::Running));
+        self.checkpoint()
+    }
+
+    /// Marks the job done, unless it was cancelled.
+    pub fn complete(&self) {
+        self.transition(|state| (!state.is_finished()).then_some(JobState::Done));
+    }
+
+    fn pause(&self) -> bool {
+        self.transition(|state| (state == JobState::Running).then_some(JobState::Paused))
+    }
+
+    fn resume(&self) -> bool {
+        self.transition(|state| (state == JobState::Paused).then_some(JobState::Running))
+    }
+
+    fn cancel(&self) -> bool {
+        self.transition(|state| (!state.is_finished()).then_some(JobState::Cancelled))
+    }
+
+    /// Applies `next` to the current state; returns whether the state changed.
+    fn transition(&self, next: impl FnOnce(JobState) -> Option<JobState>) -> bool {
+        let changed = {
+            let mut state = self.state.lock().unwrap();
+            match next(*state) {
+                Some(new_state) => {
+                    *state = new_state;
+                    // Wake workers blocked in `checkpoint` on resume and cancel alike
+                    self.resumed.notify_all();
+                    true
+                }
+                None => false,
+            }
+        };
+        if changed {
+            send_to_frontend(&self.app, self.to_json().to_string(), "job_updated");
+        }
+        changed
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "kind": self.kind,
+            "label": self.label,
+            "state": self.state(),
+            "controllable": self.controllable,
+            "created_at": self.created_at
+        })
+    }
+}
+
+/// Registers a new queued job.
+pub fn create(app: &AppHandle, kind: &'static str, label: String) -> Arc<Job> {
+    register(app, kind, label, true)
+}
+
+/// Registers a job that is only tracked; pause and cancel are refused.
+pub fn create_uncontrolled(app: &AppHandle, kind: &'static str, label: String) -> Arc<Job> {
+    register(app, kind, label, false)
+}
+
+fn register(app: &AppHandle, kind: &'static str, label: String, controllable: bool) -> Arc<Job> {
+    let job = Arc::new(Job {
+        id: Uuid::new_v4().to_string(),
+        kind,
+        label,
+        controllable,
+        created_at: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default(),
+        state: Mutex::new(JobState::Queued),
+        resumed: Condvar::new(),
+        app: app.clone(),
+    });
+
+    {
+        let mut jobs = JOBS.lock().unwrap();
+        let finished = jobs.iter().filter(|job| job.state().is_finished()).count();
+        if finished >= MAX_FINISHED_JOBS {
+            // Oldest first, since jobs are appended in creation order
+            if let Some(index) = jobs.iter().position(|job| job.state().is_finished()) {
+                jobs.remove(index);
+            }
+        }
+        jobs.push(Arc::clone(&job));
+    }
+
+    send_to_frontend(app, job.to_json().to_string(), "job_updated");
+    job
+}
+
+/// Cancels every unfinished job of `kind`.
+pub fn cancel_kind(kind: &str) {
+    let jobs: Vec<Arc<Job>> = JOBS.lock().unwrap().clone();
+    for job in jobs.iter().filter(|job| job.kind == kind && job.controllable) {
+        job.cancel();
+    }
+}
+
+fn find(job_id: &str) -> Result<Arc<Job>, String> {
+    let job = JOBS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|job| job.id == job_id)
+        .cloned()
+        .ok_or_else(|| format!("No job with id {}", job_id))?;
+    if !job.controllable {
+        return Err(format!("{} jobs cannot be paused or cancelled", job.kind));
+    }
+    Ok(job)
+}
+
+#[tauri::command]
+pub async fn list_jobs() -> Result<Value, String> {
+    let jobs = JOBS.lock().unwrap();
+    Ok(Value::Array(jobs.iter().map(|job| job.to_json()).collect()))
+}
+
+#[tauri::command]
+pub async fn pause_job(job_id: String) -> Result<Value, String> {
+    let job = find(&job_id)?;
+    if !job.pause() {
+        return Err(format!("Job {} is not running", job_id));
+    }
+    Ok(job.to_json())
+}
+
+#[tauri::command]
+pub async fn resume_job(job_id: String) -> Result<Value, String> {
+    let job = find(&job_id)?;
+    if !job.resume() {
+        return Err(format!("Job {} is not paused", job_id));
+    }
+    Ok(job.to_json())
+}
+
+#[tauri::command]
+pub async fn cancel_job(job_id: String) -> Result<Value, String> {
+    let job = find(&job_id)?;
+    if !job.cancel() {
+        return Err(format!("Job {} has already finished", job_id));
+    }
+    Ok(job.to_json())
+}
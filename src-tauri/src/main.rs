@@ -6,6 +6,27 @@ mod initialise;
 mod image_processor;
 mod search_processor;
 mod file_processor;
+mod thumbnail_cache;
+mod video_processor;
+mod heif;
+mod catalog;
+mod watcher;
+mod exclusion;
+mod media_id;
+mod scan_settings;
+mod scan_progress;
+mod jobs;
+mod media_kind;
+mod exif;
+mod raw_processor;
+mod archive;
+mod color_profile;
+mod thumbnail_profile;
+mod fast_path;
+mod animation;
+mod thumbnail_queue;
+mod blurhash;
+mod duplicates;
 use tauri::Listener;
 
 #[tokio::main]
@@ -23,13 +44,26 @@ async fn main() {
             initialise::initialize_environment,
             initialise::fetch_config,
             initialise::save_config,
+            thumbnail_profile::get_thumbnail_profile,
+            thumbnail_profile::save_thumbnail_profile,
             utils::select_folder,
             utils::read_image,
+            image_processor::get_thumbnail_for_size,
             utils::reset_all,
             utils::relaunch,
             search_processor::search_indexed_data,
             file_processor::list_files,
-            file_processor::cancel_list_files
+            file_processor::cancel_list_files,
+            jobs::list_jobs,
+            jobs::pause_job,
+            jobs::resume_job,
+            jobs::cancel_job,
+            catalog::load_catalog,
+            catalog::query_media,
+            thumbnail_cache::cache_stats,
+            thumbnail_cache::clean_thumbnail_cache,
+            thumbnail_queue::set_viewport,
+            duplicates::find_duplicates
         ])
         .run(tauri::generate_context!())
         .expect("error while running Tauri application");
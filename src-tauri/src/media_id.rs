@@ -0,0 +1,22 @@
+use crate::archive;
+use std::fs::Metadata;
+use std::path::Path;
+
+/// Deterministic id for a media file: the same file gets the same id in browse results,
+/// search results and across scans.
+pub fn media_id(path: &Path) -> String {
+    let canonical_path = archive::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    blake3::hash(canonical_path.to_string_lossy().as_bytes()).to_hex()[..32].to_string()
+}
+
+/// Device and inode of a file, which survive a move within the same filesystem.
+#[cfg(unix)]
+pub fn file_identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_identity(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
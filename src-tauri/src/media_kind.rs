@@ -0,0 +1,336 @@
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Long enough for every signature below; ISO-BMFF and RIFF brands sit at offset 8
+pub const SNIFF_LEN: usize = 16;
+
+// Lowercase extensions of each kind
+const EXTENSIONS: &[(&str, MediaKind)] = &[
+    ("jpg", MediaKind::Jpeg),
+    ("jpeg", MediaKind::Jpeg),
+    ("jpe", MediaKind::Jpeg),
+    ("jfif", MediaKind::Jpeg),
+    ("png", MediaKind::Png),
+    ("gif", MediaKind::Gif),
+    ("webp", MediaKind::WebP),
+    ("tif", MediaKind::Tiff),
+    ("tiff", MediaKind::Tiff),
+    ("bmp", MediaKind::Bmp),
+    ("dib", MediaKind::Bmp),
+    ("heic", MediaKind::Heic),
+    ("heif", MediaKind::Heic),
+    ("hif", MediaKind::Heic),
+    ("avif", MediaKind::Avif),
+    ("svg", MediaKind::Svg),
+    ("svgz", MediaKind::Svg),
+    ("cr2", MediaKind::Cr2),
+    ("nef", MediaKind::Nef),
+    ("nrw", MediaKind::Nef),
+    ("arw", MediaKind::Arw),
+    ("srf", MediaKind::Arw),
+    ("sr2", MediaKind::Arw),
+    ("dng", MediaKind::Dng),
+    ("raf", MediaKind::Raf),
+    ("rw2", MediaKind::Rw2),
+    ("mp4", MediaKind::Mp4),
+    ("m4v", MediaKind::Mp4),
+    ("mkv", MediaKind::Mkv),
+    ("webm", MediaKind::Mkv),
+    ("avi", MediaKind::Avi),
+];
+
+// Common extensions that are never media. Files named like this are skipped without opening
+// them; anything else (no extension, an unknown one or a media one) is sniffed.
+const NON_MEDIA_EXTENSIONS: &[&str] = &[
+    "txt", "md", "rst", "log", "csv", "tsv", "json", "jsonl", "xml", "yaml", "yml", "toml", "ini",
+    "cfg", "conf", "html", "htm", "css", "scss", "js", "mjs", "cjs", "jsx", "ts", "tsx", "rs",
+    "py", "pyc", "rb", "go", "java", "class", "jar", "kt", "c", "h", "cc", "cpp", "hpp", "cs",
+    "swift", "php", "pl", "lua", "sh", "bash", "zsh", "bat", "ps1", "sql", "db", "sqlite",
+    "lock", "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "odt", "ods", "odp", "rtf",
+    "epub", "mp3", "m4a", "aac", "flac", "wav", "ogg", "opus", "ttf", "otf", "woff", "woff2",
+    "exe", "dll", "so", "dylib", "o", "a", "bin", "iso", "dmg", "deb", "rpm", "gz", "bz2",
+    "xz", "zst", "tar", "tgz", "rar", "7z", "part", "crdownload", "tmp", "bak", "swp",
+];
+
+/// Concrete media formats SnapHound can show.
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Jpeg,
+    Png,
+    Gif,
+    WebP,
+    Tiff,
+    Bmp,
+    Heic,
+    Avif,
+    Svg,
+    Cr2,
+    Nef,
+    Arw,
+    Dng,
+    Raf,
+    Rw2,
+    Mp4,
+    Mkv,
+    Avi,
+}
+
+impl MediaKind {
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            MediaKind::Jpeg => "image/jpeg",
+            MediaKind::Png => "image/png",
+            MediaKind::Gif => "image/gif",
+            MediaKind::WebP => "image/webp",
+            MediaKind::Tiff => "image/tiff",
+            MediaKind::Bmp => "image/bmp",
+            MediaKind::Heic => "image/heic",
+            MediaKind::Avif => "image/avif",
+            MediaKind::Svg => "image/svg+xml",
+            MediaKind::Cr2 => "image/x-canon-cr2",
+            MediaKind::Nef => "image/x-nikon-nef",
+            MediaKind::Arw => "image/x-sony-arw",
+            MediaKind::Dng => "image/x-adobe-dng",
+            MediaKind::Raf => "image/x-fuji-raf",
+            MediaKind::Rw2 => "image/x-panasonic-rw2",
+            MediaKind::Mp4 => "video/mp4",
+            MediaKind::Mkv => "video/x-matroska",
+            MediaKind::Avi => "video/x-msvideo",
+        }
+    }
+
+    /// `image` or `video`, the coarse type used by filters, root settings and the frontend.
+    pub fn category(self) -> &'static str {
+        if self.is_video() {
+            "video"
+        } else {
+            "image"
+        }
+    }
+
+    pub fn is_video(self) -> bool {
+        matches!(self, MediaKind::Mp4 | MediaKind::Mkv | MediaKind::Avi)
+    }
+
+    /// Camera RAW formats, thumbnailed from their embedded JPEG preview.
+    pub fn is_raw(self) -> bool {
+        matches!(
+            self,
+            MediaKind::Cr2
+                | MediaKind::Nef
+                | MediaKind::Arw
+                | MediaKind::Dng
+                | MediaKind::Raf
+                | MediaKind::Rw2
+        )
+    }
+
+    /// Formats that may hold an animation; see `animation::detect`.
+    pub fn may_be_animated(self) -> bool {
+        matches!(self, MediaKind::Gif | MediaKind::WebP)
+    }
+
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        EXTENSIONS
+            .iter()
+            .map(|(_, kind)| *kind)
+            .find(|kind| kind.mime_type() == mime_type)
+    }
+
+    /// Matches lowercased extensions only; see `detect`.
+    fn from_extension(extension: &str) -> Option<Self> {
+        EXTENSIONS
+            .iter()
+            .find(|(known, _)| *known == extension)
+            .map(|(_, kind)| *kind)
+    }
+
+    fn sniff(header: &[u8]) -> Option<Self> {
+        match header {
+            [0xFF, 0xD8, 0xFF, ..] => Some(MediaKind::Jpeg),
+            [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(MediaKind::Png),
+            [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(MediaKind::Gif),
+            // Matroska and WebM share the EBML header
+            [0x1A, 0x45, 0xDF, 0xA3, ..] => Some(MediaKind::Mkv),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'A', b'V', b'I', b' ', ..] => Some(MediaKind::Avi),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(MediaKind::WebP),
+            [b'I', b'I', 0x2A, 0x00, _, _, _, _, b'C', b'R', 0x02, ..] => Some(MediaKind::Cr2),
+            [b'I', b'I', b'U', 0x00, ..] => Some(MediaKind::Rw2),
+            [b'F', b'U', b'J', b'I', b'F', b'I', b'L', b'M', ..] => Some(MediaKind::Raf),
+            [b'I', b'I', 0x2A, 0x00, ..] | [b'M', b'M', 0x00, 0x2A, ..] => Some(MediaKind::Tiff),
+            // `BM`, the file size, then two reserved words that are always zero
+            [b'B', b'M', _, _, _, _, 0, 0, 0, 0, ..] => Some(MediaKind::Bmp),
+            // SVGs starting with an XML declaration are left to the extension
+            [b'<', b's', b'v', b'g', ..] => Some(MediaKind::Svg),
+            // HEIF/AVIF stills use the same `ftyp` box as MP4, told apart by the major brand
+            [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] => match brand.get(..4) {
+                Some(b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"mif1" | b"msf1") => {
+                    Some(MediaKind::Heic)
+                }
+                Some(b"avif" | b"avis") => Some(MediaKind::Avif),
+                Some(_) => Some(MediaKind::Mp4),
+                None => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Extensions of every still image kind, handed to the indexer. RAW files are left out, the
+/// indexer cannot read them.
+pub fn image_extensions() -> Vec<&'static str> {
+    EXTENSIONS
+        .iter()
+        .filter(|(_, kind)| !kind.is_video() && !kind.is_raw())
+        .map(|(extension, _)| *extension)
+        .collect()
+}
+
+/// A detected media type. `extension_mismatch` is set when the file has an extension that
+/// names a different format than its content, e.g. a PNG saved as `photo.jpg`.
+#[derive(Clone, Copy, Debug)]
+pub struct MediaType {
+    pub kind: MediaKind,
+    pub extension_mismatch: bool,
+}
+
+impl MediaType {
+    pub fn mime_type(&self) -> &'static str {
+        self.kind.mime_type()
+    }
+
+    pub fn category(&self) -> &'static str {
+        self.kind.category()
+    }
+}
+
+/// Detects the media type of `path` from its header, falling back to the extension (in any
+/// case) when the header is unreadable or unrecognised. `None` for non-media files; those with
+/// a known non-media extension are not opened.
+pub fn detect(path: &Path) -> Option<MediaType> {
+    let non_media = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| NON_MEDIA_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str()));
+    if non_media {
+        return None;
+    }
+    detect_with_header(path, read_header(path).as_deref())
+}
+
+/// `detect` for content that is not a plain file, such as an archive member: `header` holds
+/// its first bytes, `path` supplies the extension.
+pub fn detect_with_header(path: &Path, header: Option<&[u8]>) -> Option<MediaType> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    let by_extension = extension.as_deref().and_then(MediaKind::from_extension);
+
+    let sniffed = header.and_then(MediaKind::sniff);
+    match sniffed {
+        // NEF, ARW and DNG are plain TIFF files inside
+        Some(MediaKind::Tiff) if by_extension.is_some_and(MediaKind::is_raw) => by_extension.map(|kind| MediaType {
+            kind,
+            extension_mismatch: false,
+        }),
+        Some(kind) => Some(MediaType {
+            kind,
+            extension_mismatch: extension.is_some() && by_extension != Some(kind),
+        }),
+        // Truncated or still being written; trust the extension so the file gets a tile
+        None => by_extension.map(|kind| MediaType {
+            kind,
+            extension_mismatch: false,
+        }),
+    }
+}
+
+fn read_header(path: &Path) -> Option<Vec<u8>> {
+    let mut header = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)
+        .ok()?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut header)
+        .ok()?;
+    Some(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sniff(header: &[u8]) -> Option<MediaKind> {
+        MediaKind::sniff(header)
+    }
+
+    #[test]
+    fn sniffs_signatures() {
+        assert_eq!(sniff(b"\xFF\xD8\xFF\xE0\0\x10JFIF"), Some(MediaKind::Jpeg));
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"), Some(MediaKind::Png));
+        assert_eq!(sniff(b"GIF87a\x01\0"), Some(MediaKind::Gif));
+        assert_eq!(sniff(b"GIF89a\x01\0"), Some(MediaKind::Gif));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some(MediaKind::WebP));
+        assert_eq!(sniff(b"RIFF\0\0\0\0AVI LIST"), Some(MediaKind::Avi));
+        assert_eq!(sniff(b"\x1A\x45\xDF\xA3\x01\0\0\0"), Some(MediaKind::Mkv));
+        assert_eq!(sniff(b"BM\x36\0\x0C\0\0\0\0\0\x36\0"), Some(MediaKind::Bmp));
+        assert_eq!(sniff(b"<svg xmlns="), Some(MediaKind::Svg));
+        assert_eq!(sniff(b"FUJIFILMCCD-RAW "), Some(MediaKind::Raf));
+        assert_eq!(sniff(b"IIU\0\x18\0\0\0"), Some(MediaKind::Rw2));
+    }
+
+    #[test]
+    fn tells_tiff_based_formats_apart() {
+        assert_eq!(sniff(b"II\x2A\0\x10\0\0\0CR\x02\0"), Some(MediaKind::Cr2));
+        assert_eq!(sniff(b"II\x2A\0\x08\0\0\0\x0F\0"), Some(MediaKind::Tiff));
+        assert_eq!(sniff(b"MM\0\x2A\0\0\0\x08"), Some(MediaKind::Tiff));
+    }
+
+    #[test]
+    fn tells_iso_bmff_brands_apart() {
+        assert_eq!(sniff(b"\0\0\0\x18ftypheic\0\0\0\0"), Some(MediaKind::Heic));
+        assert_eq!(sniff(b"\0\0\0\x18ftypmif1\0\0\0\0"), Some(MediaKind::Heic));
+        assert_eq!(sniff(b"\0\0\0\x1cftypavif\0\0\0\0"), Some(MediaKind::Avif));
+        assert_eq!(sniff(b"\0\0\0\x20ftypisom\0\0\x02\0"), Some(MediaKind::Mp4));
+        assert_eq!(sniff(b"\0\0\0\x20ftyp"), None);
+    }
+
+    #[test]
+    fn leaves_unknown_and_short_headers_alone() {
+        assert_eq!(sniff(b"%PDF-1.7"), None);
+        assert_eq!(sniff(b"<?xml version"), None);
+        assert_eq!(sniff(b"BM"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn flags_extension_mismatches() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+        let media = detect_with_header(Path::new("photo.JPG"), Some(png)).unwrap();
+        assert_eq!((media.kind, media.extension_mismatch), (MediaKind::Png, true));
+
+        let media = detect_with_header(Path::new("photo.PNG"), Some(png)).unwrap();
+        assert_eq!((media.kind, media.extension_mismatch), (MediaKind::Png, false));
+
+        // No extension to contradict
+        let media = detect_with_header(Path::new("photo"), Some(png)).unwrap();
+        assert_eq!((media.kind, media.extension_mismatch), (MediaKind::Png, false));
+    }
+
+    #[test]
+    fn keeps_raw_kinds_of_tiff_containers() {
+        let media = detect_with_header(Path::new("DSC_0001.NEF"), Some(b"MM\0\x2A\0\0\0\x08")).unwrap();
+        assert_eq!((media.kind, media.extension_mismatch), (MediaKind::Nef, false));
+    }
+
+    #[test]
+    fn falls_back_to_the_extension() {
+        let media = detect_with_header(Path::new("clip.webm"), Some(b"")).unwrap();
+        assert_eq!((media.kind, media.extension_mismatch), (MediaKind::Mkv, false));
+        assert!(detect_with_header(Path::new("notes.txt"), None).is_none());
+        assert!(detect(Path::new("/nonexistent/notes.txt")).is_none());
+    }
+}
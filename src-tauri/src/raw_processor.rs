@@ -0,0 +1,258 @@
+use crate::color_profile;
+use crate::exif::{self, TiffReader};
+use crate::image_processor::Decoded;
+use image::ImageFormat;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+
+// Guards against IFD loops and corrupt SubIFD lists
+const MAX_IFDS: usize = 32;
+// Previews are a few MB at most; anything larger is sensor data or corruption
+const MAX_PREVIEW_BYTES: u64 = 64 * 1024 * 1024;
+// Enough of a candidate to find its frame header behind the EXIF block
+const PREVIEW_HEADER_BYTES: u64 = 128 * 1024;
+
+// JPEG-compressed strips, old-style (6) and new-style (7)
+const COMPRESSION_JPEG: [u32; 2] = [6, 7];
+// CFA and LinearRaw mark an IFD holding the sensor data itself
+const PHOTOMETRIC_SENSOR: [u32; 2] = [32803, 34892];
+// Panasonic RW2 keeps its full-size JPEG as an opaque IFD0 entry
+const TAG_RW2_JPEG: u16 = 0x002E;
+// Canon CR2 marks its raw IFD with slice information
+const TAG_CR2_SLICE: u16 = 0xC640;
+// RAF header: big-endian offset and length of the embedded JPEG
+const RAF_JPEG_OFFSET: u64 = 84;
+
+/// Location of a JPEG embedded in a RAW file.
+struct Preview {
+    offset: u64,
+    length: u64,
+}
+
+/// Decodes the largest embedded JPEG preview of a RAW file (CR2, NEF, ARW, DNG, RAF, RW2),
+/// rotated by the file's orientation. Nothing is demosaiced.
+pub fn extract_preview(raw_path: &str) -> Result<Decoded, String> {
+    let mut file = BufReader::new(File::open(raw_path).map_err(|e| e.to_string())?);
+    let mut magic = [0u8; 8];
+    file.read_exact(&mut magic).map_err(|e| e.to_string())?;
+
+    // RAF wraps a plain JPEG (with its own EXIF) ahead of a TIFF-like raw block
+    let (mut previews, orientation) = if &magic == b"FUJIFILM" {
+        let mut location = [0u8; 8];
+        file.seek(SeekFrom::Start(RAF_JPEG_OFFSET))
+            .and_then(|_| file.read_exact(&mut location))
+            .map_err(|e| e.to_string())?;
+        let preview = Preview {
+            offset: u32::from_be_bytes([location[0], location[1], location[2], location[3]]) as u64,
+            length: u32::from_be_bytes([location[4], location[5], location[6], location[7]]) as u64,
+        };
+        (vec![preview], None)
+    } else {
+        let mut tiff = TiffReader::new(&mut file, 0)
+            .ok_or_else(|| format!("{} is not a TIFF-based RAW file", raw_path))?;
+        let orientation = exif::tiff_orientation(&mut tiff);
+        (find_previews(&mut tiff), orientation)
+    };
+
+    // The full-size preview is the largest one
+    previews.sort_by_key(|preview| Reverse(preview.length));
+    for preview in previews.iter().filter(|p| p.length > 0 && p.length <= MAX_PREVIEW_BYTES) {
+        let Some(jpeg) = read_preview(&mut file, preview) else {
+            continue;
+        };
+        if let Ok(img) = image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg) {
+            let img = match color_profile::embedded_profile(&jpeg) {
+                Some(icc) => color_profile::to_srgb(img, &icc),
+                None => img,
+            };
+            let orientation = orientation.or_else(|| exif::jpeg_orientation(&jpeg));
+            return Ok(Decoded::upright(img, orientation.unwrap_or(1)));
+        }
+    }
+
+    Err(format!("No embedded JPEG preview found in {}", raw_path))
+}
+
+/// Walks IFD0, its chain and every SubIFD for JPEG previews.
+fn find_previews<R: Read + Seek>(tiff: &mut TiffReader<R>) -> Vec<Preview> {
+    let mut previews = Vec::new();
+    let mut pending: Vec<u64> = tiff.first_ifd().into_iter().collect();
+    let mut visited = HashSet::new();
+
+    while let Some(offset) = pending.pop() {
+        if offset == 0 || visited.len() >= MAX_IFDS || !visited.insert(offset) {
+            continue;
+        }
+        let Some((entries, next)) = tiff.read_ifd(offset) else {
+            continue;
+        };
+        pending.push(next);
+        pending.extend(tiff.longs(&entries, exif::TAG_SUB_IFDS, MAX_IFDS));
+
+        if let (Some(offset), Some(length)) = (
+            tiff.scalar(&entries, exif::TAG_JPEG_OFFSET),
+            tiff.scalar(&entries, exif::TAG_JPEG_LENGTH),
+        ) {
+            previews.push(Preview {
+                offset: offset as u64,
+                length: length as u64,
+            });
+        }
+
+        // Single-strip JPEG images, unless they hold the sensor data
+        let is_sensor_data = tiff
+            .scalar(&entries, exif::TAG_PHOTOMETRIC)
+            .is_some_and(|photometric| PHOTOMETRIC_SENSOR.contains(&photometric))
+            || entries.iter().any(|entry| entry.tag == TAG_CR2_SLICE);
+        let is_jpeg = tiff
+            .scalar(&entries, exif::TAG_COMPRESSION)
+            .is_some_and(|compression| COMPRESSION_JPEG.contains(&compression));
+        let single_strip = entries
+            .iter()
+            .any(|entry| entry.tag == exif::TAG_STRIP_OFFSETS && entry.count == 1);
+        if is_jpeg && single_strip && !is_sensor_data {
+            if let (Some(offset), Some(length)) = (
+                tiff.scalar(&entries, exif::TAG_STRIP_OFFSETS),
+                tiff.scalar(&entries, exif::TAG_STRIP_BYTE_COUNTS),
+            ) {
+                previews.push(Preview {
+                    offset: offset as u64,
+                    length: length as u64,
+                });
+            }
+        }
+
+        if let Some(entry) = entries.iter().find(|entry| entry.tag == TAG_RW2_JPEG) {
+            previews.push(Preview {
+                offset: tiff.value_offset(entry),
+                length: entry.count as u64,
+            });
+        }
+    }
+
+    previews
+}
+
+/// Reads a preview if it is a DCT JPEG; lossless sensor JPEGs are skipped before being read
+/// in full.
+fn read_preview<R: Read + Seek>(file: &mut R, preview: &Preview) -> Option<Vec<u8>> {
+    let mut header = vec![0u8; preview.length.min(PREVIEW_HEADER_BYTES) as usize];
+    file.seek(SeekFrom::Start(preview.offset)).ok()?;
+    file.read_exact(&mut header).ok()?;
+    if !exif::is_dct_jpeg(&header) {
+        return None;
+    }
+
+    let mut jpeg = header;
+    jpeg.resize(preview.length as usize, 0);
+    let read_so_far = preview.length.min(PREVIEW_HEADER_BYTES) as usize;
+    file.read_exact(&mut jpeg[read_so_far..]).ok()?;
+    Some(jpeg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    const UNDEFINED: u16 = 7;
+
+    /// Writes a little-endian IFD of (tag, type, count, value) entries at `offset`.
+    fn write_ifd(tiff: &mut Vec<u8>, offset: usize, entries: &[(u16, u16, u32, u32)], next: u32) {
+        let mut ifd = (entries.len() as u16).to_le_bytes().to_vec();
+        for &(tag, field_type, count, value) in entries {
+            ifd.extend_from_slice(&tag.to_le_bytes());
+            ifd.extend_from_slice(&field_type.to_le_bytes());
+            ifd.extend_from_slice(&count.to_le_bytes());
+            ifd.extend_from_slice(&value.to_le_bytes());
+        }
+        ifd.extend_from_slice(&next.to_le_bytes());
+        tiff.resize(tiff.len().max(offset + ifd.len()), 0);
+        tiff[offset..offset + ifd.len()].copy_from_slice(&ifd);
+    }
+
+    fn previews(tiff: &[u8]) -> Vec<(u64, u64)> {
+        let mut reader = TiffReader::new(Cursor::new(tiff), 0).unwrap();
+        let mut previews: Vec<(u64, u64)> = find_previews(&mut reader)
+            .iter()
+            .map(|preview| (preview.offset, preview.length))
+            .collect();
+        previews.sort();
+        previews
+    }
+
+    #[test]
+    fn finds_previews_across_ifds_and_sub_ifds() {
+        let mut tiff = b"II\x2A\x00\x08\x00\x00\x00".to_vec();
+        // IFD0: a thumbnail, an RW2-style opaque JPEG and two SubIFDs; its chain loops back
+        write_ifd(
+            &mut tiff,
+            8,
+            &[
+                (TAG_RW2_JPEG, UNDEFINED, 4096, 6000),
+                (exif::TAG_SUB_IFDS, LONG, 2, 100),
+                (exif::TAG_JPEG_OFFSET, LONG, 1, 1000),
+                (exif::TAG_JPEG_LENGTH, LONG, 1, 200),
+            ],
+            8,
+        );
+        tiff.resize(100, 0);
+        tiff.extend_from_slice(&[200, 0, 0, 0, 44, 1, 0, 0]);
+        // A full-size JPEG strip
+        write_ifd(
+            &mut tiff,
+            200,
+            &[
+                (exif::TAG_COMPRESSION, SHORT, 1, 7),
+                (exif::TAG_STRIP_OFFSETS, LONG, 1, 5000),
+                (exif::TAG_STRIP_BYTE_COUNTS, LONG, 1, 90000),
+            ],
+            0,
+        );
+        // Lossless JPEG sensor data
+        write_ifd(
+            &mut tiff,
+            300,
+            &[
+                (exif::TAG_COMPRESSION, SHORT, 1, 7),
+                (exif::TAG_PHOTOMETRIC, SHORT, 1, 32803),
+                (exif::TAG_STRIP_OFFSETS, LONG, 1, 7000),
+                (exif::TAG_STRIP_BYTE_COUNTS, LONG, 1, 900000),
+            ],
+            0,
+        );
+
+        assert_eq!(previews(&tiff), vec![(1000, 200), (5000, 90000), (6000, 4096)]);
+    }
+
+    #[test]
+    fn skips_multi_strip_and_cr2_raw_ifds() {
+        let mut tiff = b"II\x2A\x00\x08\x00\x00\x00".to_vec();
+        write_ifd(
+            &mut tiff,
+            8,
+            &[
+                (exif::TAG_COMPRESSION, SHORT, 1, 6),
+                (exif::TAG_STRIP_OFFSETS, LONG, 2, 500),
+                (exif::TAG_STRIP_BYTE_COUNTS, LONG, 2, 600),
+            ],
+            64,
+        );
+        write_ifd(
+            &mut tiff,
+            64,
+            &[
+                (exif::TAG_COMPRESSION, SHORT, 1, 6),
+                (exif::TAG_STRIP_OFFSETS, LONG, 1, 700),
+                (exif::TAG_STRIP_BYTE_COUNTS, LONG, 1, 800),
+                (TAG_CR2_SLICE, SHORT, 3, 0),
+            ],
+            0,
+        );
+        assert!(previews(&tiff).is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use crate::utils::send_to_frontend;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::AppHandle;
+
+// Frequent enough for a smooth bar without flooding the webview
+const EMIT_INTERVAL: Duration = Duration::from_millis(250);
+// Files/sec is measured over this window so it follows slow and fast stretches of a scan
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// How a single media item was produced during a scan.
+pub enum ItemOutcome {
+    /// Thumbnail served from the catalog or the thumbnail cache
+    Cached,
+    /// Source decoded and thumbnailed; carries the bytes read
+    Generated(u64),
+    Failed,
+}
+
+/// Counters for one `list_files` run, reported as `scan_progress` events while scanning and a
+/// final `scan_summary` event.
+pub struct ScanProgress {
+    started: Instant,
+    directories_total: AtomicUsize,
+    directories_done: AtomicUsize,
+    files_discovered: AtomicUsize,
+    files_processed: AtomicUsize,
+    generated: AtomicUsize,
+    cached: AtomicUsize,
+    failed: AtomicUsize,
+    walk_errors: AtomicUsize,
+    bytes_read: AtomicU64,
+    // (time, files processed) samples inside RATE_WINDOW
+    samples: Mutex<VecDeque<(Instant, usize)>>,
+    last_emit: Mutex<Option<Instant>>,
+}
+
+impl ScanProgress {
+    pub fn new(directories_total: usize) -> Self {
+        let started = Instant::now();
+        Self {
+            started,
+            directories_total: AtomicUsize::new(directories_total),
+            directories_done: AtomicUsize::new(0),
+            files_discovered: AtomicUsize::new(0),
+            files_processed: AtomicUsize::new(0),
+            generated: AtomicUsize::new(0),
+            cached: AtomicUsize::new(0),
+            failed: AtomicUsize::new(0),
+            walk_errors: AtomicUsize::new(0),
+            bytes_read: AtomicU64::new(0),
+            samples: Mutex::new(VecDeque::from([(started, 0)])),
+            last_emit: Mutex::new(None),
+        }
+    }
+
+    pub fn directory_done(&self) {
+        self.directories_done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn files_discovered(&self, count: usize) {
+        self.files_discovered.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Unreadable directories or entries skipped while walking.
+    pub fn walk_error(&self) {
+        self.walk_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record(&self, outcome: ItemOutcome) {
+        match outcome {
+            ItemOutcome::Cached => self.cached.fetch_add(1, Ordering::Relaxed),
+            ItemOutcome::Generated(bytes) => {
+                self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+                self.generated.fetch_add(1, Ordering::Relaxed)
+            }
+            ItemOutcome::Failed => self.failed.fetch_add(1, Ordering::Relaxed),
+        };
+        self.files_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sends a `scan_progress` event, at most every EMIT_INTERVAL unless `force` is set.
+    pub fn emit(&self, app: &AppHandle, force: bool) {
+        let now = Instant::now();
+        {
+            let mut last_emit = self.last_emit.lock().unwrap();
+            if !force && last_emit.is_some_and(|last| now - last < EMIT_INTERVAL) {
+                return;
+            }
+            *last_emit = Some(now);
+        }
+
+        let files_processed = self.files_processed.load(Ordering::Relaxed);
+        let files_per_sec = self.rate(now, files_processed);
+        let remaining = self
+            .files_discovered
+            .load(Ordering::Relaxed)
+            .saturating_sub(files_processed);
+        // Only covers files discovered so far; directories still to walk add to it
+        let eta_secs = (files_per_sec > 0.0).then(|| remaining as f64 / files_per_sec);
+
+        let payload = json!({
+            "directories_done": self.directories_done.load(Ordering::Relaxed),
+            "directories_total": self.directories_total.load(Ordering::Relaxed),
+            "files_discovered": self.files_discovered.load(Ordering::Relaxed),
+            "files_processed": files_processed,
+            "thumbnails_generated": self.generated.load(Ordering::Relaxed),
+            "thumbnails_cached": self.cached.load(Ordering::Relaxed),
+            "thumbnails_failed": self.failed.load(Ordering::Relaxed),
+            "bytes_read": self.bytes_read.load(Ordering::Relaxed),
+            "files_per_sec": files_per_sec,
+            "eta_secs": eta_secs,
+            "elapsed_secs": self.started.elapsed().as_secs_f64()
+        });
+        send_to_frontend(app, payload.to_string(), "scan_progress");
+    }
+
+    /// Sends the final `scan_summary` event.
+    pub fn finish(&self, app: &AppHandle, cancelled: bool) {
+        self.emit(app, true);
+
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let files_processed = self.files_processed.load(Ordering::Relaxed);
+        let failed = self.failed.load(Ordering::Relaxed);
+        let walk_errors = self.walk_errors.load(Ordering::Relaxed);
+        let payload = json!({
+            "cancelled": cancelled,
+            "elapsed_secs": elapsed,
+            "directories": self.directories_done.load(Ordering::Relaxed),
+            "files_processed": files_processed,
+            "thumbnails_generated": self.generated.load(Ordering::Relaxed),
+            "thumbnails_cached": self.cached.load(Ordering::Relaxed),
+            "bytes_read": self.bytes_read.load(Ordering::Relaxed),
+            "files_per_sec": if elapsed > 0.0 { files_processed as f64 / elapsed } else { 0.0 },
+            "errors": {
+                "thumbnails_failed": failed,
+                "walk_errors": walk_errors,
+                "total": failed + walk_errors
+            }
+        });
+        send_to_frontend(app, payload.to_string(), "scan_summary");
+    }
+
+    fn rate(&self, now: Instant, files_processed: usize) -> f64 {
+        let mut samples = self.samples.lock().unwrap();
+        samples.push_back((now, files_processed));
+        // Keep one sample older than the window so the rate spans all of it
+        while samples.len() > 2 && now - samples[1].0 >= RATE_WINDOW {
+            samples.pop_front();
+        }
+
+        let (oldest_time, oldest_count) = samples[0];
+        let seconds = (now - oldest_time).as_secs_f64();
+        if seconds > 0.0 {
+            files_processed.saturating_sub(oldest_count) as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+}
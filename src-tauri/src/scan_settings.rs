@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+
+/// A `priority_paths` entry as stored in the config: either a bare pattern, which follows the
+/// global `recursive` flag, or an object carrying its own settings. Entries are written back in
+/// the form they were given so the config round-trips through `fetch_config`/`save_config`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum PriorityPathConfig {
+    Plain(String),
+    Detailed(RootSettings),
+}
+
+/// Scan settings of a single priority path.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RootSettings {
+    pub path: String,
+    /// Levels below each matched directory to descend; `None` is unlimited. Defaults to the
+    /// global `recursive` flag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_depth: Option<usize>,
+    /// `image`/`video`; all types when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_types: Option<Vec<String>>,
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    /// Overrides the global `exclude.skip_hidden` for this root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub include_hidden: Option<bool>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl PriorityPathConfig {
+    pub fn resolve(&self, recursive: bool) -> RootSettings {
+        match self {
+            PriorityPathConfig::Plain(path) => RootSettings {
+                path: path.clone(),
+                max_depth: None,
+                media_types: None,
+                follow_symlinks: false,
+                include_hidden: None,
+                enabled: true,
+            }
+            .with_default_depth(recursive),
+            PriorityPathConfig::Detailed(settings) => settings.clone().with_default_depth(recursive),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            PriorityPathConfig::Plain(path) => path,
+            PriorityPathConfig::Detailed(settings) => &settings.path,
+        }
+    }
+}
+
+impl RootSettings {
+    fn with_default_depth(mut self, recursive: bool) -> Self {
+        if self.max_depth.is_none() && !recursive {
+            self.max_depth = Some(1);
+        }
+        self
+    }
+
+    pub fn is_recursive(&self) -> bool {
+        self.max_depth.is_none_or(|depth| depth > 1)
+    }
+
+    pub fn allows_type(&self, file_type: &str) -> bool {
+        self.media_types
+            .as_ref()
+            .is_none_or(|types| types.iter().any(|t| t == file_type))
+    }
+
+    /// Whether `path` lies within `max_depth` levels below `directory`.
+    pub fn within_depth(&self, directory: &Path, path: &Path) -> bool {
+        match (self.max_depth, path.strip_prefix(directory)) {
+            (_, Err(_)) => false,
+            (None, Ok(_)) => true,
+            (Some(max_depth), Ok(relative)) => relative.components().count() <= max_depth,
+        }
+    }
+}
+
+/// The configured priority paths, in config order, including disabled ones.
+pub fn priority_path_configs(config: &Value) -> Vec<PriorityPathConfig> {
+    // Parsed one by one so a single malformed entry doesn't disable every root
+    config["priority_paths"]
+        .as_array()
+        .map(|entries| {
+            entries
+                .iter()
+                .filter_map(|entry| serde_json::from_value(entry.clone()).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Settings of every enabled priority path.
+pub fn enabled_roots(config: &Value) -> Vec<RootSettings> {
+    let recursive = config["recursive"].as_bool().unwrap_or(false);
+    priority_path_configs(config)
+        .iter()
+        .map(|entry| entry.resolve(recursive))
+        .filter(|settings| settings.enabled)
+        .collect()
+}
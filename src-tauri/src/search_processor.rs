@@ -2,19 +2,26 @@ use std::process::{Command, Stdio};
 use std::sync::{Arc, Mutex};
 use std::collections::HashSet;
 use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
 use std::thread;
 use tauri::AppHandle;
 use serde_json::json;
 use crate::initialise::{EnvPaths, fetch_config};
-use crate::utils::send_to_frontend;
-use crate::file_processor::cancel_list_files;
-use crate::image_processor::process_thumbnail;
+use crate::archive;
+use crate::exclusion;
+use crate::scan_settings::enabled_roots;
+use crate::utils::{get_file_type, send_to_frontend};
+use crate::file_processor::build_media_item;
+use crate::jobs::{self, Job};
+use crate::media_kind;
+use crate::thumbnail_cache;
 use serde_json::Value;
-use uuid::Uuid;
 
 lazy_static::lazy_static! {
 	static ref PYTHON_PROCESS: Arc<Mutex<Option<std::process::Child>>> = Arc::new(Mutex::new(None));
 	static ref SENT_RESULTS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+	// The indexer reports start and end on stdout; tracked as an `indexing` job in between
+	static ref INDEX_JOB: Mutex<Option<Arc<Job>>> = Mutex::new(None);
 }
 
 fn handle_process_output(reader: impl BufRead, app: &AppHandle, event_type: &str, prefix: &str) {
@@ -28,21 +35,35 @@ fn handle_process_output(reader: impl BufRead, app: &AppHandle, event_type: &str
 						.iter()
                         .filter_map(|path| path.as_str())
                         .filter(|file_path| sent_results.insert(file_path.to_string())) // Ignore duplicates
-						.map(|file_path| json!({
-							"id": Uuid::new_v4().to_string(),
-							"file_path": file_path,
-							"path": process_thumbnail(file_path),
-							"type": "image",
-							"searched_result":true
-						}))
+						.filter_map(|file_path| {
+							// Archive members are indexed through staged thumbnails
+							let file_path = archive::member_for_staged(Path::new(file_path))
+								.unwrap_or_else(|| file_path.to_string());
+							let file_path = Path::new(&file_path);
+							let media = get_file_type(file_path)?;
+							let mut item = build_media_item(app, file_path, &media);
+							item["searched_result"] = json!(true);
+							Some(item)
+						})
 						.collect();
 
                     if !results.is_empty() {
+						thumbnail_cache::schedule_save();
 						send_to_frontend(app, json!(results).to_string(), "searched_result");
                     }
 				}
 			}
 		} else if line.contains("Starting Index process") || line.contains("Index Completed") {
+			let mut index_job = INDEX_JOB.lock().unwrap();
+			if line.contains("Starting Index process") {
+				if index_job.is_none() {
+					let job = jobs::create_uncontrolled(app, "indexing", "Indexing priority paths".to_string());
+					job.run();
+					*index_job = Some(job);
+				}
+			} else if let Some(job) = index_job.take() {
+				job.complete();
+			}
 			send_to_frontend(app, format!("{}", line), event_type);
 		}
 	}
@@ -52,11 +73,18 @@ fn handle_process_output(reader: impl BufRead, app: &AppHandle, event_type: &str
 pub async fn start_python_process(app: AppHandle) {
 	let paths = EnvPaths::new(); // Ensure this struct has `python_binary` and `search_path`
     let config = fetch_config().await.unwrap();
+    // Disabled roots are left out entirely; the rest carry their depth/type/symlink settings
+    let roots = enabled_roots(&config);
 
     // Construct the JSON argument for the Python script
     let python_args_json = json!({
         "index": true,
-        "priority_paths": config["priority_paths"]
+        // Archive members staged for indexing, see `archive::sync_indexer`
+        "path": [&paths.archive_index_path],
+        "priority_paths": roots.iter().map(|root| &root.path).collect::<Vec<_>>(),
+        "root_settings": roots,
+        "image_extensions": media_kind::image_extensions(),
+        "exclude": exclusion::indexer_args(&config)
     }).to_string();
 	
 	match Command::new(&paths.python_binary)
@@ -137,8 +165,6 @@ pub async fn index_data() {
 #[tauri::command]
 pub async fn search_indexed_data(search_query: String, app: AppHandle) {
     if !search_query.trim().is_empty() {
-		cancel_list_files(app.clone()).await.ok();
-
         // Reset the sent results tracking
         SENT_RESULTS.lock().unwrap().clear();
 
@@ -0,0 +1,493 @@
+use crate::animation::Animation;
+use crate::archive;
+use crate::initialise::EnvPaths;
+use crate::thumbnail_profile;
+use crate::utils::modified_nanos;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE: &str = "index.json";
+// Part of every cache key along with the thumbnail profile; bump when thumbnails of an
+// unchanged source would come out differently, e.g. 2 applied EXIF orientation and ICC profiles,
+// 3 bounded tall width-fit thumbnails and resized through DCT-scaled decodes
+const PIPELINE_VERSION: u32 = 3;
+
+/// Long-edge sizes of the thumbnail pyramid, generated on demand next to the base thumbnail.
+pub const PYRAMID_LEVELS: [u32; 4] = [128, 256, 512, 1024];
+
+// Config key holding `max_mb`, the disk budget of the cache
+const CONFIG_KEY: &str = "thumbnail_cache";
+const DEFAULT_MAX_MB: u64 = 2048;
+// Files this young may belong to a thumbnail being generated right now, not yet recorded
+const STRAY_GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+// `schedule_save` writes the index at most this often
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+// `lookup` results since startup, for `cache_stats`; thumbnails served from the catalog count
+// as hits too
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static SAVE_SCHEDULED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    // Maps the canonical source path to the cache entry generated for it
+    static ref CACHE_INDEX: Mutex<HashMap<String, CacheEntry>> = Mutex::new(load_index());
+    // Keys served from the catalog since `last_used` was last updated; applied in one pass over
+    // the index before it is swept or saved
+    static ref SERVED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CacheEntry {
+    pub key: String,
+    pub size: u64,
+    pub mtime: u64,
+    // Dimensions of the source, so cache hits don't need a decode to report them
+    #[serde(default)]
+    pub width: u32,
+    #[serde(default)]
+    pub height: u32,
+    #[serde(default)]
+    pub orientation: Option<u32>,
+    // 1 for stills; `None` for entries from before animation detection
+    #[serde(default)]
+    pub frame_count: Option<u32>,
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+    // BlurHash of the thumbnail; `None` for entries from before placeholders
+    #[serde(default)]
+    pub placeholder: Option<String>,
+    // dHash of the source, compared by `duplicates::find_duplicates`. Stored under a new name
+    // since earlier entries hashed the thumbnail
+    #[serde(default, rename = "source_phash")]
+    pub phash: Option<u64>,
+    // Unix time (s) the entry was last generated or served, for LRU eviction
+    #[serde(default)]
+    pub last_used: u64,
+}
+
+impl CacheEntry {
+    pub fn animation(&self) -> Option<Animation> {
+        match (self.frame_count, self.duration_ms) {
+            (Some(frame_count), Some(duration_ms)) if frame_count > 1 => Some(Animation {
+                frame_count,
+                duration_ms,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Identity of a source file as seen by the cache: canonical path, size and mtime (ns).
+pub struct SourceFingerprint {
+    pub canonical_path: PathBuf,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+impl SourceFingerprint {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let canonical_path = archive::canonicalize(path)?;
+        let (size, mtime) = if archive::is_member_path(&canonical_path) {
+            archive::member_metadata(&canonical_path)?
+        } else {
+            let metadata = fs::metadata(&canonical_path)?;
+            (metadata.len(), modified_nanos(&metadata))
+        };
+
+        Ok(Self {
+            canonical_path,
+            size,
+            mtime,
+        })
+    }
+
+    pub fn cache_key(&self) -> String {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(self.canonical_path.to_string_lossy().as_bytes());
+        hasher.update(&self.size.to_le_bytes());
+        hasher.update(&self.mtime.to_le_bytes());
+        hasher.update(pipeline_version().as_bytes());
+        hasher.finalize().to_hex()[..32].to_string()
+    }
+}
+
+/// Identifies the pipeline and profile thumbnails are currently made with. Catalog rows record
+/// it, so a profile change makes them stale like it does cache keys.
+pub fn pipeline_version() -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&PIPELINE_VERSION.to_le_bytes());
+    hasher.update(&thumbnail_profile::current().digest());
+    hasher.finalize().to_hex()[..16].to_string()
+}
+
+/// Location of a cache entry, sharded by the first two characters of the key. The extension
+/// follows the current profile's format; entries of another format are stale anyway.
+pub fn entry_path(key: &str) -> PathBuf {
+    EnvPaths::new()
+        .thumbnail_path
+        .join(&key[..2])
+        .join(format!("{}.{}", key, thumbnail_profile::current().format.extension()))
+}
+
+/// Location of a pyramid level of a cache entry (see `image_processor::thumbnail_for_size`).
+pub fn level_path(key: &str, level: u32) -> PathBuf {
+    let base = entry_path(key);
+    base.with_file_name(format!(
+        "{}-{}.{}",
+        key,
+        level,
+        thumbnail_profile::current().format.extension()
+    ))
+}
+
+/// Location of the animated WebP preview of a cache entry; always WebP, whatever the profile's
+/// format.
+pub fn preview_path(key: &str) -> PathBuf {
+    entry_path(key).with_file_name(format!("{}-preview.webp", key))
+}
+
+/// Every file a cache entry may own: the thumbnail, its pyramid levels and animated preview.
+fn entry_files(key: &str) -> impl Iterator<Item = PathBuf> + '_ {
+    [entry_path(key), preview_path(key)]
+        .into_iter()
+        .chain(PYRAMID_LEVELS.into_iter().map(move |level| level_path(key, level)))
+}
+
+/// Removes the files of a cache entry, returning the bytes freed.
+fn remove_files(key: &str) -> u64 {
+    entry_files(key)
+        .filter_map(|path| {
+            let size = fs::metadata(&path).ok()?.len();
+            fs::remove_file(&path).ok().map(|_| size)
+        })
+        .sum()
+}
+
+fn disk_bytes(key: &str) -> u64 {
+    entry_files(key)
+        .filter_map(|path| fs::metadata(path).ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Returns the cache entry for `fingerprint` if neither the source nor the pipeline changed
+/// since it was generated.
+pub fn lookup(fingerprint: &SourceFingerprint) -> Option<CacheEntry> {
+    let entry = lookup_current(fingerprint);
+    let counter = if entry.is_some() { &HITS } else { &MISSES };
+    counter.fetch_add(1, Ordering::Relaxed);
+    entry
+}
+
+fn lookup_current(fingerprint: &SourceFingerprint) -> Option<CacheEntry> {
+    let mut index = CACHE_INDEX.lock().unwrap();
+    let entry = index.get_mut(fingerprint.canonical_path.to_string_lossy().as_ref())?;
+    // The key covers size, mtime, pipeline version and profile
+    if entry.key != fingerprint.cache_key() || !entry_path(&entry.key).exists() {
+        return None;
+    }
+
+    entry.last_used = unix_now();
+    Some(entry.clone())
+}
+
+/// Notes that the thumbnails with `keys` were served from the catalog without a `lookup`, so
+/// LRU eviction and the hit rate see them as used.
+pub fn touch<'a>(keys: impl IntoIterator<Item = &'a str>) {
+    let mut served = SERVED.lock().unwrap();
+    let mut count = 0;
+    for key in keys {
+        served.insert(key.to_string());
+        count += 1;
+    }
+    HITS.fetch_add(count, Ordering::Relaxed);
+}
+
+fn apply_served() {
+    let served = std::mem::take(&mut *SERVED.lock().unwrap());
+    if served.is_empty() {
+        return;
+    }
+    let now = unix_now();
+    for entry in CACHE_INDEX.lock().unwrap().values_mut() {
+        if served.contains(&entry.key) {
+            entry.last_used = now;
+        }
+    }
+}
+
+/// Records a freshly generated entry, removing the thumbnail of the previous version of the source.
+pub fn record(
+    fingerprint: &SourceFingerprint,
+    key: &str,
+    (width, height): (u32, u32),
+    orientation: u32,
+    animation: Option<Animation>,
+    placeholder: Option<String>,
+    phash: Option<u64>,
+) {
+    let entry = CacheEntry {
+        key: key.to_string(),
+        size: fingerprint.size,
+        mtime: fingerprint.mtime,
+        width,
+        height,
+        orientation: Some(orientation),
+        frame_count: Some(animation.map_or(1, |animation| animation.frame_count)),
+        duration_ms: Some(animation.map_or(0, |animation| animation.duration_ms)),
+        placeholder,
+        phash,
+        last_used: unix_now(),
+    };
+
+    let previous = CACHE_INDEX
+        .lock()
+        .unwrap()
+        .insert(fingerprint.canonical_path.to_string_lossy().into_owned(), entry);
+
+    if let Some(previous) = previous {
+        if previous.key != key {
+            remove_files(&previous.key);
+        }
+    }
+}
+
+/// Stores the placeholder computed for an entry cached before placeholders existed, unless the
+/// entry was replaced meanwhile.
+pub fn set_placeholder(fingerprint: &SourceFingerprint, key: &str, placeholder: &str) {
+    let mut index = CACHE_INDEX.lock().unwrap();
+    if let Some(entry) = index.get_mut(fingerprint.canonical_path.to_string_lossy().as_ref()) {
+        if entry.key == key {
+            entry.placeholder = Some(placeholder.to_string());
+        }
+    }
+}
+
+/// The cache's disk budget in bytes, from the config's `thumbnail_cache.max_mb`.
+pub fn budget_bytes(config: &Value) -> u64 {
+    config[CONFIG_KEY]["max_mb"].as_u64().unwrap_or(DEFAULT_MAX_MB) * 1024 * 1024
+}
+
+/// What a `sweep` removed.
+#[derive(Default, Debug)]
+pub struct SweepReport {
+    pub orphans: usize,
+    pub strays: usize,
+    pub evicted: usize,
+    pub bytes_freed: u64,
+}
+
+/// Removes entries whose source no longer exists and files no entry owns (left behind by e.g.
+/// a change of thumbnail format), then evicts the least recently used entries until the cache
+/// fits in `budget_bytes`. Evicted thumbnails are marked pending in the catalog and regenerated
+/// when their file is next scanned.
+pub fn sweep(budget_bytes: u64) -> SweepReport {
+    let mut report = SweepReport::default();
+    apply_served();
+    // Snapshot, so thumbnails can be generated while the disk is walked
+    let entries: Vec<(String, CacheEntry)> = CACHE_INDEX
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(path, entry)| (path.clone(), entry.clone()))
+        .collect();
+
+    let mut archives = HashMap::new();
+    let (orphans, mut live): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .partition(|(path, _)| !source_exists(path, &mut archives));
+    for (path, entry) in orphans {
+        if forget(&path, &entry.key) {
+            report.orphans += 1;
+            report.bytes_freed += remove_files(&entry.key);
+        }
+    }
+
+    let owned: HashSet<PathBuf> = live.iter().flat_map(|(_, entry)| entry_files(&entry.key)).collect();
+    for (path, size) in cache_files() {
+        let settled = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .is_ok_and(|modified| modified.elapsed().unwrap_or_default() >= STRAY_GRACE_PERIOD);
+        if !owned.contains(&path) && settled && fs::remove_file(&path).is_ok() {
+            report.strays += 1;
+            report.bytes_freed += size;
+        }
+    }
+
+    // Members' thumbnails are hard-linked into the archive index (see `archive::stage`), so
+    // evicting them frees nothing; they still count towards the budget
+    let mut total: u64 = live.iter().map(|(_, entry)| disk_bytes(&entry.key)).sum();
+    live.retain(|(path, _)| !archive::is_member_path(Path::new(path)));
+    live.sort_by_key(|(_, entry)| entry.last_used);
+    let mut evicted = Vec::new();
+    for (path, entry) in live {
+        if total <= budget_bytes {
+            break;
+        }
+        if forget(&path, &entry.key) {
+            let freed = remove_files(&entry.key);
+            total = total.saturating_sub(freed);
+            report.evicted += 1;
+            report.bytes_freed += freed;
+            evicted.push(entry.key);
+        }
+    }
+    if let Err(e) = crate::catalog::clear_thumbnail_keys(&evicted) {
+        println!("Failed to mark evicted thumbnails pending: {}", e);
+    }
+    report
+}
+
+/// Removes the index entry of `path` unless it was regenerated under another key meanwhile.
+fn forget(path: &str, key: &str) -> bool {
+    let mut index = CACHE_INDEX.lock().unwrap();
+    if index.get(path).is_some_and(|entry| entry.key == key) {
+        index.remove(path);
+        true
+    } else {
+        false
+    }
+}
+
+/// Whether a source still exists; each archive is only listed once per sweep.
+fn source_exists(path: &str, archives: &mut HashMap<PathBuf, HashSet<String>>) -> bool {
+    match archive::split_member_path(path) {
+        Some((archive_path, name)) => archives
+            .entry(archive_path.to_path_buf())
+            .or_insert_with(|| archive::member_names(archive_path).unwrap_or_default())
+            .contains(name),
+        None => Path::new(path).exists(),
+    }
+}
+
+/// Files in the cache's shard directories with their sizes. Placeholders and the index live
+/// at the top level and are not included.
+fn cache_files() -> Vec<(PathBuf, u64)> {
+    let Ok(shards) = fs::read_dir(EnvPaths::new().thumbnail_path) else {
+        return Vec::new();
+    };
+    shards
+        .flatten()
+        .filter(|shard| shard.file_type().is_ok_and(|file_type| file_type.is_dir()))
+        .filter_map(|shard| fs::read_dir(shard.path()).ok())
+        .flat_map(|files| files.flatten())
+        .filter_map(|file| {
+            let metadata = file.metadata().ok()?;
+            metadata.is_file().then(|| (file.path(), metadata.len()))
+        })
+        .collect()
+}
+
+/// Entry count, bytes on disk, budget and hit rate of the cache.
+pub fn stats(budget_bytes: u64) -> Value {
+    let entries = CACHE_INDEX.lock().unwrap().len();
+    let bytes: u64 = cache_files().iter().map(|(_, size)| size).sum();
+    let hits = HITS.load(Ordering::Relaxed);
+    let misses = MISSES.load(Ordering::Relaxed);
+    let lookups = hits + misses;
+    json!({
+        "entries": entries,
+        "bytes": bytes,
+        "budget_bytes": budget_bytes,
+        "hits": hits,
+        "misses": misses,
+        "hit_rate": if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 }
+    })
+}
+
+async fn configured_budget() -> u64 {
+    crate::initialise::fetch_config()
+        .await
+        .map(|config| budget_bytes(&config))
+        .unwrap_or(DEFAULT_MAX_MB * 1024 * 1024)
+}
+
+/// Entry count, bytes on disk, budget and lookup hit rate since startup.
+#[tauri::command]
+pub async fn cache_stats() -> Result<Value, String> {
+    let budget_bytes = configured_budget().await;
+    tokio::task::spawn_blocking(move || stats(budget_bytes))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Runs a `sweep` now rather than after the next scan. Returns what it removed and the stats
+/// afterwards.
+#[tauri::command]
+pub async fn clean_thumbnail_cache() -> Result<Value, String> {
+    let budget_bytes = configured_budget().await;
+    tokio::task::spawn_blocking(move || {
+        let report = sweep(budget_bytes);
+        save_index().map_err(|e| e.to_string())?;
+        Ok(json!({
+            "orphans": report.orphans,
+            "strays": report.strays,
+            "evicted": report.evicted,
+            "bytes_freed": report.bytes_freed,
+            "stats": stats(budget_bytes)
+        }))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+pub fn save_index() -> io::Result<()> {
+    let paths = EnvPaths::new();
+    let index_path = paths.thumbnail_path.join(INDEX_FILE);
+    let tmp_path = index_path.with_extension("json.tmp");
+    apply_served();
+
+    let json_string = {
+        let index = CACHE_INDEX.lock().unwrap();
+        serde_json::to_string(&*index).map_err(io::Error::other)?
+    };
+
+    // Write to a temp file first so a crash never leaves a truncated index behind
+    fs::write(&tmp_path, json_string)?;
+    fs::rename(&tmp_path, &index_path)
+}
+
+/// Saves the index `SAVE_DELAY` from now on a background thread, once for however many calls
+/// arrive meanwhile; for callers like search results that add entries in many small batches.
+pub fn schedule_save() {
+    if SAVE_SCHEDULED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    thread::spawn(|| {
+        thread::sleep(SAVE_DELAY);
+        // Cleared first, so entries added while saving schedule another save
+        SAVE_SCHEDULED.store(false, Ordering::Relaxed);
+        if let Err(e) = save_index() {
+            println!("Failed to save thumbnail index: {}", e);
+        }
+    });
+}
+
+/// Forgets every entry; used when the whole cache directory is wiped.
+pub fn clear() {
+    CACHE_INDEX.lock().unwrap().clear();
+}
+
+fn load_index() -> HashMap<String, CacheEntry> {
+    let paths = EnvPaths::new();
+    fs::read_to_string(paths.thumbnail_path.join(INDEX_FILE))
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
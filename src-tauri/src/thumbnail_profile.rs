@@ -0,0 +1,364 @@
+use crate::fast_path;
+use crate::initialise::fetch_config;
+use crate::utils::send_to_frontend;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, ImageOutputFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Cursor;
+use std::sync::{Arc, RwLock};
+use tauri::AppHandle;
+use webp::Encoder;
+
+// Config key holding the profile
+const CONFIG_KEY: &str = "thumbnail";
+const SIZE_RANGE: std::ops::RangeInclusive<u32> = 16..=4096;
+// Width fit still bounds the height at this multiple of `size`, so a 1000x20000 screenshot
+// doesn't turn into a giant thumbnail
+const WIDTH_FIT_MAX_ASPECT: u32 = 4;
+
+lazy_static::lazy_static! {
+    static ref PROFILE: RwLock<Arc<ThumbnailProfile>> = RwLock::new(Arc::new(ThumbnailProfile::default()));
+}
+
+/// How thumbnails are produced, from the `thumbnail` config key. The whole profile is part of
+/// every cache key, so changing any of it regenerates thumbnails as files are next scanned.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct ThumbnailProfile {
+    /// Target size in pixels; what it bounds depends on `fit`
+    pub size: u32,
+    pub fit: FitMode,
+    pub filter: ResizeFilter,
+    /// Applied in order after the resize
+    pub adjustments: Vec<Adjustment>,
+    pub format: OutputFormat,
+    /// 1-100; ignored for PNG
+    pub quality: u8,
+}
+
+impl Default for ThumbnailProfile {
+    // The pipeline as it was before profiles existed
+    fn default() -> Self {
+        Self {
+            size: 512,
+            fit: FitMode::Width,
+            filter: ResizeFilter::Triangle,
+            adjustments: vec![
+                Adjustment::Contrast { factor: 1.15 },
+                Adjustment::Brightness { amount: 10.0 },
+            ],
+            format: OutputFormat::Webp,
+            quality: 75,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    /// Limits the width, for masonry grids; very tall images are also bounded in height
+    Width,
+    /// Fits within a `size` x `size` box
+    Box,
+    /// Center-cropped to a `size` x `size` square
+    Square,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    fn filter_type(self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// One step of post-resize tone adjustment, e.g. `{ "type": "contrast", "factor": 1.15 }`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Adjustment {
+    /// Scales each channel's distance from mid-gray; 1.0 leaves the image unchanged
+    Contrast { factor: f32 },
+    /// Added to each channel, -255 to 255
+    Brightness { amount: f32 },
+    /// Unsharp mask
+    Sharpen { sigma: f32, threshold: i32 },
+}
+
+impl Adjustment {
+    fn apply(self, img: RgbaImage) -> RgbaImage {
+        match self {
+            Adjustment::Contrast { factor } => {
+                map_channels(img, |value| ((value / 255.0 - 0.5) * factor + 0.5) * 255.0)
+            }
+            Adjustment::Brightness { amount } => map_channels(img, |value| value + amount),
+            Adjustment::Sharpen { sigma, threshold } => imageops::unsharpen(&img, sigma, threshold),
+        }
+    }
+}
+
+/// Applies `adjust` to the color channels through a lookup table; alpha is left alone.
+fn map_channels(mut img: RgbaImage, adjust: impl Fn(f32) -> f32) -> RgbaImage {
+    let lookup_table: [u8; 256] = std::array::from_fn(|value| adjust(value as f32).clamp(0.0, 255.0) as u8);
+    for pixel in img.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = lookup_table[*channel as usize];
+        }
+    }
+    img
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Webp,
+    Jpeg,
+    Png,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Webp => "webp",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+        }
+    }
+}
+
+impl ThumbnailProfile {
+    /// The config's profile and a warning for each field that had to fall back to its default.
+    /// A bad field only resets itself, not the rest of the profile.
+    pub fn from_config(config: &Value) -> (Self, Vec<String>) {
+        let mut warnings = Vec::new();
+        let mut fields = match serde_json::to_value(Self::default()) {
+            Ok(Value::Object(fields)) => fields,
+            _ => return (Self::default(), warnings),
+        };
+        match &config[CONFIG_KEY] {
+            Value::Null => {}
+            Value::Object(configured) => {
+                for (name, value) in configured {
+                    if !fields.contains_key(name) {
+                        warnings.push(format!("Ignoring unknown thumbnail setting '{}'", name));
+                        continue;
+                    }
+                    // Missing fields default, so this checks the one field on its own
+                    match serde_json::from_value::<Self>(serde_json::json!({ name: value })) {
+                        Ok(_) => {
+                            fields.insert(name.clone(), value.clone());
+                        }
+                        Err(e) => warnings.push(format!("Invalid thumbnail {} ({}), using the default", name, e)),
+                    }
+                }
+            }
+            _ => warnings.push("The thumbnail setting must be an object, using the default profile".to_string()),
+        }
+
+        let mut profile: Self = serde_json::from_value(Value::Object(fields)).unwrap_or_default();
+        let default = Self::default();
+        for (field, problem) in profile.problems() {
+            warnings.push(format!("{}, using the default", problem));
+            match field {
+                "size" => profile.size = default.size,
+                _ => profile.quality = default.quality,
+            }
+        }
+        (profile, warnings)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self.problems().into_iter().next() {
+            Some((_, problem)) => Err(problem),
+            None => Ok(()),
+        }
+    }
+
+    /// Out-of-range fields, by name.
+    fn problems(&self) -> Vec<(&'static str, String)> {
+        let mut problems = Vec::new();
+        if !SIZE_RANGE.contains(&self.size) {
+            problems.push((
+                "size",
+                format!("Thumbnail size must be between {} and {}", SIZE_RANGE.start(), SIZE_RANGE.end()),
+            ));
+        }
+        if !(1..=100).contains(&self.quality) {
+            problems.push(("quality", "Thumbnail quality must be between 1 and 100".to_string()));
+        }
+        problems
+    }
+
+    /// The profile of a pyramid level: bounded by `level` on the long edge, square crops stay
+    /// square.
+    pub fn for_level(&self, level: u32) -> Self {
+        let mut profile = self.clone();
+        profile.size = level;
+        if !matches!(profile.fit, FitMode::Square) {
+            profile.fit = FitMode::Box;
+        }
+        profile
+    }
+
+    /// Bytes identifying the profile, hashed into cache keys.
+    pub fn digest(&self) -> Vec<u8> {
+        serde_json::to_vec(self).unwrap_or_default()
+    }
+
+    /// Resizes, adjusts and encodes a decoded, upright source.
+    pub fn render(&self, img: DynamicImage) -> Result<Vec<u8>, String> {
+        self.encode(self.render_frame(img))
+    }
+
+    /// Resizes and adjusts without encoding, e.g. for the frames of an animated preview.
+    pub fn render_frame(&self, img: DynamicImage) -> RgbaImage {
+        let resized = self.resize(img);
+        self.adjustments
+            .iter()
+            .fold(resized.to_rgba8(), |img, adjustment| adjustment.apply(img))
+    }
+
+    /// Size of the thumbnail of an upright `width` x `height` source. Sources are never scaled
+    /// up.
+    pub fn output_size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        match self.fit {
+            FitMode::Width if width > self.size || height > self.size * WIDTH_FIT_MAX_ASPECT => {
+                fit_within((width, height), (self.size, self.size * WIDTH_FIT_MAX_ASPECT))
+            }
+            FitMode::Box if width > self.size || height > self.size => {
+                fit_within((width, height), (self.size, self.size))
+            }
+            FitMode::Square => {
+                let edge = self.size.min(width).min(height);
+                (edge, edge)
+            }
+            _ => (width, height),
+        }
+    }
+
+    fn resize(&self, img: DynamicImage) -> DynamicImage {
+        let (width, height) = (img.width(), img.height());
+        let (target_width, target_height) = self.output_size((width, height));
+        let img = match self.fit {
+            FitMode::Square if width != height => {
+                let edge = width.min(height);
+                img.crop_imm((width - edge) / 2, (height - edge) / 2, edge, edge)
+            }
+            _ => img,
+        };
+        if img.dimensions() == (target_width, target_height) {
+            return img;
+        }
+
+        let filter = self.filter.filter_type();
+        fast_path::resize(&img, target_width, target_height, filter)
+            .unwrap_or_else(|| img.resize_exact(target_width, target_height, filter))
+    }
+
+    /// Encodes a frame from `render_frame` in the profile's format.
+    pub fn encode(&self, img: RgbaImage) -> Result<Vec<u8>, String> {
+        let (width, height) = img.dimensions();
+        match self.format {
+            OutputFormat::Webp => Ok(Encoder::from_rgba(img.as_ref(), width, height)
+                .encode(self.quality as f32)
+                .to_vec()),
+            OutputFormat::Jpeg | OutputFormat::Png => {
+                let (img, format) = match self.format {
+                    // JPEG has no alpha channel
+                    OutputFormat::Jpeg => (
+                        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(img).into_rgb8()),
+                        ImageOutputFormat::Jpeg(self.quality),
+                    ),
+                    _ => (DynamicImage::ImageRgba8(img), ImageOutputFormat::Png),
+                };
+                let mut data = Vec::new();
+                img.write_to(&mut Cursor::new(&mut data), format)
+                    .map_err(|e| e.to_string())?;
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// Largest size with the aspect ratio of `width` x `height` that fits within the maximums.
+fn fit_within((width, height): (u32, u32), (max_width, max_height): (u32, u32)) -> (u32, u32) {
+    let ratio = (max_width as f64 / width as f64).min(max_height as f64 / height as f64);
+    let scale = |length: u32| ((length as f64 * ratio).round() as u32).max(1);
+    (scale(width), scale(height))
+}
+
+/// The profile thumbnails are currently generated with.
+pub fn current() -> Arc<ThumbnailProfile> {
+    Arc::clone(&PROFILE.read().unwrap())
+}
+
+/// Makes the config's profile current; called whenever the config is (re)loaded. Fields that
+/// fell back to their defaults are reported as `config_warning`s.
+pub fn load(app: &AppHandle, config: &Value) {
+    let (profile, warnings) = ThumbnailProfile::from_config(config);
+    for warning in warnings {
+        send_to_frontend(app, warning, "config_warning");
+    }
+    *PROFILE.write().unwrap() = Arc::new(profile);
+}
+
+#[tauri::command]
+pub async fn get_thumbnail_profile() -> Result<ThumbnailProfile, String> {
+    Ok((*current()).clone())
+}
+
+/// Saves the profile to the config and makes it current. Existing thumbnails are regenerated
+/// as their files are next scanned.
+#[tauri::command]
+pub async fn save_thumbnail_profile(profile: ThumbnailProfile) -> Result<ThumbnailProfile, String> {
+    profile.validate()?;
+
+    // Keep the priority paths and everything else already in the file
+    let mut config = fetch_config()
+        .await
+        .ok()
+        .filter(Value::is_object)
+        .unwrap_or_else(|| serde_json::json!({}));
+    config[CONFIG_KEY] = serde_json::to_value(&profile).map_err(|e| e.to_string())?;
+    crate::initialise::write_config(&config)?;
+
+    *PROFILE.write().unwrap() = Arc::new(profile.clone());
+    Ok(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn bad_fields_fall_back_on_their_own() {
+        let config = json!({ "thumbnail": { "size": 256, "quality": 0, "fit": "stretch", "colour": 1 } });
+        let (profile, warnings) = ThumbnailProfile::from_config(&config);
+        assert_eq!(profile.size, 256);
+        assert_eq!(profile.quality, ThumbnailProfile::default().quality);
+        assert!(matches!(profile.fit, FitMode::Width));
+        assert_eq!(warnings.len(), 3);
+    }
+
+    #[test]
+    fn missing_profile_is_the_default_without_warnings() {
+        let (profile, warnings) = ThumbnailProfile::from_config(&json!({}));
+        assert_eq!(profile.digest(), ThumbnailProfile::default().digest());
+        assert!(warnings.is_empty());
+    }
+}
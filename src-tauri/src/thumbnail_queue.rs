@@ -0,0 +1,337 @@
+use crate::catalog;
+use crate::file_processor::thumbnail_entry;
+use crate::jobs::{self, Job, JobState};
+use crate::media_kind::MediaKind;
+use crate::scan_progress::{ItemOutcome, ScanProgress};
+use crate::utils::send_to_frontend;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::AppHandle;
+
+// Idle workers and waiting scans wake this often to notice resumed and cancelled jobs
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// Set by the first `set_viewport`; until then scans thumbnail inline (see `viewport_reported`)
+static VIEWPORT_REPORTED: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref QUEUE: Mutex<Queue> = Mutex::new(Queue::default());
+    static ref WORK_AVAILABLE: Condvar = Condvar::new();
+    // The `thumbnail_warmup` job files queued by `set_viewport` join while it runs
+    static ref WARMUP: Mutex<Option<Arc<Batch>>> = Mutex::new(None);
+}
+
+/// Order thumbnails are generated in; each runs before the next.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Priority {
+    /// In the viewport
+    Visible,
+    /// Just outside it, about to scroll in
+    Nearby,
+    /// Everything else a scan found
+    Background,
+}
+
+impl Priority {
+    fn lane(self) -> usize {
+        self as usize
+    }
+}
+
+struct Task {
+    kind: MediaKind,
+    priority: Priority,
+    batch: Option<Arc<Batch>>,
+}
+
+#[derive(Default)]
+struct Queue {
+    // One FIFO of paths per priority. Re-prioritised paths are pushed again, so entries whose
+    // priority no longer matches their task are stale and skipped
+    lanes: [VecDeque<String>; 3],
+    tasks: HashMap<String, Task>,
+    // Paths promoted by the last `set_viewport`, applied to files queued after it too
+    viewport: HashMap<String, Priority>,
+    // Lane entries of paused jobs by job id, set aside until the job resumes or is cancelled so
+    // they aren't walked again on every wake-up
+    parked: HashMap<String, Parked>,
+    workers_started: bool,
+}
+
+struct Parked {
+    batch: Arc<Batch>,
+    // (lane, path) in the order they were popped
+    entries: Vec<(usize, String)>,
+}
+
+impl Queue {
+    fn prioritise(&mut self, path: &str, priority: Priority) {
+        if let Some(task) = self.tasks.get_mut(path) {
+            if task.priority != priority {
+                task.priority = priority;
+                self.lanes[priority.lane()].push_back(path.to_string());
+            }
+        }
+    }
+
+    /// The next runnable task, highest priority first. Work of a paused job waits for it to
+    /// resume and that of a cancelled job is dropped, except that scans keep thumbnailing the
+    /// viewport.
+    fn next_task(&mut self) -> Option<(String, Task)> {
+        self.unpark();
+        for lane in 0..self.lanes.len() {
+            while let Some(path) = self.lanes[lane].pop_front() {
+                let held = match self.tasks.get(&path) {
+                    Some(task) if task.priority.lane() == lane => task
+                        .batch
+                        .as_ref()
+                        .filter(|batch| task.priority == Priority::Background || batch.job.kind != "scan")
+                        .cloned(),
+                    _ => continue,
+                };
+
+                match held.map(|batch| (batch.job.state(), batch)) {
+                    Some((JobState::Cancelled, batch)) => {
+                        self.tasks.remove(&path);
+                        batch.finish(None);
+                    }
+                    Some((JobState::Paused, batch)) => {
+                        self.parked
+                            .entry(batch.job.id.clone())
+                            .or_insert_with(|| Parked {
+                                batch: Arc::clone(&batch),
+                                entries: Vec::new(),
+                            })
+                            .entries
+                            .push((lane, path));
+                    }
+                    _ => return self.tasks.remove(&path).map(|task| (path, task)),
+                }
+            }
+        }
+        None
+    }
+
+    /// Puts the parked entries of jobs that are no longer paused back at the front of their
+    /// lanes, in their original order. Those of cancelled jobs are dropped by `next_task`.
+    fn unpark(&mut self) {
+        let resumed: Vec<String> = self
+            .parked
+            .iter()
+            .filter(|(_, parked)| parked.batch.job.state() != JobState::Paused)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in resumed {
+            if let Some(parked) = self.parked.remove(&id) {
+                for (lane, path) in parked.entries.into_iter().rev() {
+                    self.lanes[lane].push_front(path);
+                }
+            }
+        }
+    }
+}
+
+/// The thumbnails queued by one job: a scan, which counts their outcomes in its progress, or a
+/// viewport warm-up. The job waits for them before finishing.
+pub struct Batch {
+    app: AppHandle,
+    job: Arc<Job>,
+    progress: Option<Arc<ScanProgress>>,
+    pending: Mutex<usize>,
+    drained: Condvar,
+}
+
+impl Batch {
+    pub fn new(app: &AppHandle, job: &Arc<Job>, progress: Option<&Arc<ScanProgress>>) -> Arc<Self> {
+        Arc::new(Self {
+            app: app.clone(),
+            job: Arc::clone(job),
+            progress: progress.cloned(),
+            pending: Mutex::new(0),
+            drained: Condvar::new(),
+        })
+    }
+
+    fn finish(&self, outcome: Option<ItemOutcome>) {
+        if let (Some(progress), Some(outcome)) = (&self.progress, outcome) {
+            progress.record(outcome);
+            progress.emit(&self.app, false);
+        }
+        let mut pending = self.pending.lock().unwrap();
+        *pending = pending.saturating_sub(1);
+        if *pending == 0 {
+            self.drained.notify_all();
+        }
+    }
+
+    /// Blocks until every thumbnail of the batch is done, or its job is cancelled.
+    pub fn wait(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        while *pending > 0 && !self.job.is_cancelled() {
+            pending = self.drained.wait_timeout(pending, POLL_INTERVAL).unwrap().0;
+        }
+    }
+}
+
+/// Queues the thumbnail of a catalogued file, at background priority unless it is in the
+/// viewport. Returns false if it is already queued.
+pub fn enqueue(app: &AppHandle, path: String, kind: MediaKind, batch: Option<Arc<Batch>>) -> bool {
+    let mut queue = QUEUE.lock().unwrap();
+    if queue.tasks.contains_key(&path) {
+        return false;
+    }
+
+    let priority = queue.viewport.get(&path).copied().unwrap_or(Priority::Background);
+    if let Some(batch) = &batch {
+        *batch.pending.lock().unwrap() += 1;
+    }
+    queue.lanes[priority.lane()].push_back(path.clone());
+    queue.tasks.insert(path, Task { kind, priority, batch });
+    start_workers(app, &mut queue);
+    WORK_AVAILABLE.notify_one();
+    true
+}
+
+/// Starts the worker pool on first use, one worker per core.
+fn start_workers(app: &AppHandle, queue: &mut Queue) {
+    if queue.workers_started {
+        return;
+    }
+    queue.workers_started = true;
+    for _ in 0..num_cpus::get().max(1) {
+        let app = app.clone();
+        thread::spawn(move || work(app));
+    }
+}
+
+fn work(app: AppHandle) {
+    loop {
+        let (path, task) = {
+            let mut queue = QUEUE.lock().unwrap();
+            loop {
+                if let Some(next) = queue.next_task() {
+                    break next;
+                }
+                queue = WORK_AVAILABLE.wait_timeout(queue, POLL_INTERVAL).unwrap().0;
+            }
+        };
+
+        let outcome = generate(&app, &path, task.kind);
+        if let Some(batch) = task.batch {
+            batch.finish(outcome);
+        }
+    }
+}
+
+/// Whether the frontend has reported a viewport. Until it has, nothing would prioritise queued
+/// thumbnails or listen for `thumbnail_ready`, so scans generate them inline instead.
+pub fn viewport_reported() -> bool {
+    VIEWPORT_REPORTED.load(Ordering::Relaxed)
+}
+
+/// Thumbnails a queued file and sends its updated item as a `thumbnail_ready` event. Files
+/// removed from the catalog since they were queued are skipped.
+fn generate(app: &AppHandle, path: &str, kind: MediaKind) -> Option<ItemOutcome> {
+    let mut entry = catalog::get(path)?;
+    let outcome = thumbnail_entry(app, &mut entry, kind);
+    send_to_frontend(app, entry.to_media_item().to_string(), "thumbnail_ready");
+    Some(outcome)
+}
+
+/// Reports the media ids in the viewport (`visible_ids`) and just outside it (`nearby_ids`).
+/// Their thumbnails jump the queue, and files without a thumbnail yet are queued; files that
+/// left the viewport go back to background priority. Returns how many were queued or
+/// promoted.
+#[tauri::command]
+pub async fn set_viewport(
+    app: AppHandle,
+    visible_ids: Vec<String>,
+    nearby_ids: Option<Vec<String>>,
+) -> Result<usize, String> {
+    VIEWPORT_REPORTED.store(true, Ordering::Relaxed);
+    let entries: Vec<_> = visible_ids
+        .iter()
+        .map(|id| (id, Priority::Visible))
+        .chain(nearby_ids.iter().flatten().map(|id| (id, Priority::Nearby)))
+        .filter_map(|(id, priority)| catalog::get_by_id(id).map(|entry| (entry, priority)))
+        .collect();
+
+    let mut viewport = HashMap::new();
+    for (entry, priority) in &entries {
+        // Visible wins over nearby for ids reported as both
+        viewport.entry(entry.path.clone()).or_insert(*priority);
+    }
+
+    let promoted;
+    let missing: Vec<_> = {
+        let mut queue = QUEUE.lock().unwrap();
+        let previous = std::mem::replace(&mut queue.viewport, viewport.clone());
+        for path in previous.keys().filter(|path| !viewport.contains_key(*path)) {
+            queue.prioritise(path, Priority::Background);
+        }
+        for (path, priority) in &viewport {
+            queue.prioritise(path, *priority);
+        }
+        WORK_AVAILABLE.notify_all();
+
+        promoted = viewport.keys().filter(|path| queue.tasks.contains_key(*path)).count();
+        entries
+            .into_iter()
+            .filter(|(entry, _)| !queue.tasks.contains_key(&entry.path))
+            .filter(|(entry, _)| !entry.is_current(entry.size, entry.mtime))
+            .collect()
+    };
+
+    let mut queued = promoted;
+    let missing: Vec<_> = missing
+        .into_iter()
+        .filter_map(|(entry, _)| Some((entry.path, MediaKind::from_mime_type(entry.mime_type.as_deref()?)?)))
+        .collect();
+    if !missing.is_empty() {
+        // Held while queuing, so the warm-up can't complete in between
+        let mut warmup = WARMUP.lock().unwrap();
+        let batch = warmup_batch(&app, &mut warmup);
+        for (path, kind) in missing {
+            if enqueue(&app, path, kind, Some(Arc::clone(&batch))) {
+                queued += 1;
+            }
+        }
+    }
+    Ok(queued)
+}
+
+/// The running (or paused) `thumbnail_warmup` job, or a new one that completes once its files
+/// are thumbnailed.
+fn warmup_batch(app: &AppHandle, warmup: &mut Option<Arc<Batch>>) -> Arc<Batch> {
+    if let Some(batch) = warmup
+        .as_ref()
+        .filter(|batch| matches!(batch.job.state(), JobState::Running | JobState::Paused))
+    {
+        return Arc::clone(batch);
+    }
+
+    let job = jobs::create(app, "thumbnail_warmup", "Generating thumbnails for the viewport".to_string());
+    job.run();
+    let batch = Batch::new(app, &job, None);
+    *warmup = Some(Arc::clone(&batch));
+    let waiting = Arc::clone(&batch);
+    thread::spawn(move || {
+        loop {
+            waiting.wait();
+            let mut warmup = WARMUP.lock().unwrap();
+            // Files may have joined since the queue drained
+            if *waiting.pending.lock().unwrap() > 0 && !waiting.job.is_cancelled() {
+                continue;
+            }
+            if warmup.as_ref().is_some_and(|batch| Arc::ptr_eq(batch, &waiting)) {
+                *warmup = None;
+            }
+            break;
+        }
+        waiting.job.complete();
+    });
+    batch
+}
@@ -1,18 +1,25 @@
 use rfd::FileDialog;
 use serde_json::json;
 use std::env::temp_dir;
-use std::fs;
+use std::fs::{self, Metadata};
 use std::path::{Path, PathBuf};
 use std::process::{exit, Child, Command, Stdio};
 use tauri::{AppHandle, Emitter, Manager};
 use uuid::Uuid;
 use rayon::prelude::*;
 use walkdir::WalkDir;
+use glob::{MatchOptions, Pattern};
 use std::io::{BufRead, BufReader};
+use std::time::UNIX_EPOCH;
 
 use crate::initialise::EnvPaths;
 use crate::initialise::fetch_config;
 use crate::image_processor::process_thumbnail;
+use crate::thumbnail_cache;
+use crate::catalog;
+use crate::archive;
+use crate::media_kind::{self, MediaType};
+use crate::scan_settings::RootSettings;
 
 pub fn send_to_frontend(app_handle: &AppHandle, message: String, event_type: &str) {
     println!("{}", message);
@@ -29,79 +36,219 @@ pub async fn select_folder() -> String {
     }
 }
 
-pub fn expand_paths(paths: Vec<String>) -> Vec<PathBuf> {
-    let mut directories = Vec::new();
-
-    for mut path_str in paths {
-        let recursive = path_str.ends_with("/*");
-
-        // Handle special cases for home directory
-        let expanded_path = if path_str == "~/*" {
-            // Just return home dir, we'll process the wildcard below
-            dirs::home_dir().unwrap_or_else(|| PathBuf::from("~"))
-        } else if path_str.starts_with("~/") {
-            // Trim pattern suffixes if present
-            if recursive {
-                path_str.truncate(path_str.len() - 2);
-            }
-            
-            // Expand home directory
-            dirs::home_dir()
-                .map(|home| home.join(&path_str[2..]))
-                .unwrap_or_else(|| PathBuf::from(&path_str))
-        } else {
-            // Trim pattern suffixes for non-home paths
-            if recursive {
-                path_str.truncate(path_str.len() - 2);
+/// Result of expanding the configured priority path globs.
+#[derive(Default)]
+pub struct ExpandedPaths {
+    /// Directories to scan, walked up to the `max_depth` of their root
+    pub directories: Vec<PathBuf>,
+    /// Files matched directly by a pattern, e.g. `~/Downloads/**/*.png`
+    pub files: Vec<PathBuf>,
+    /// Patterns whose last component is a wildcard, used to vet files that show up later
+    pub file_patterns: Vec<Pattern>,
+    /// Directories where such later files can appear, on top of `directories`
+    pub parent_directories: Vec<PathBuf>,
+    /// Human readable problems: invalid patterns, unknown variables, patterns matching nothing
+    pub warnings: Vec<String>,
+}
+
+impl ExpandedPaths {
+    /// Whether a file (possibly created after expansion) falls under the configured paths.
+    pub fn contains(&self, path: &Path, settings: &RootSettings) -> bool {
+        let in_directory = self
+            .directories
+            .iter()
+            .any(|directory| settings.within_depth(directory, path));
+
+        in_directory
+            || self
+                .file_patterns
+                .iter()
+                .any(|pattern| pattern.matches_path_with(path, GLOB_OPTIONS))
+    }
+}
+
+// Like a shell: `*` stays within one directory and never matches hidden entries
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
+/// Expands priority paths written as globs: `~`, `$HOME`/`${VAR}`, brace alternation
+/// (`~/Pictures/{2023,2024}`), `**`, character classes and extension filters.
+pub fn expand_priority_paths(paths: &[String]) -> ExpandedPaths {
+    let mut expanded = ExpandedPaths::default();
+
+    for path_str in paths {
+        let matched_before = expanded.directories.len() + expanded.files.len();
+
+        for alternative in expand_braces(path_str) {
+            let (pattern, has_glob) = match shellexpand::full(&alternative) {
+                Ok(pattern) => escape_existing_prefix(&pattern),
+                Err(e) => {
+                    expanded.warnings.push(format!("{}: {}", path_str, e));
+                    continue;
+                }
+            };
+
+            let entries = match glob::glob_with(&pattern, GLOB_OPTIONS) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    expanded.warnings.push(format!("{}: invalid pattern: {}", path_str, e));
+                    continue;
+                }
+            };
+
+            for entry in entries.flatten() {
+                if entry.is_dir() {
+                    expanded.directories.push(entry);
+                } else if entry.is_file() {
+                    expanded.files.push(entry);
+                }
             }
-            
-            PathBuf::from(&path_str)
-        };
-        
-        if expanded_path.is_dir() {
-            directories.push(expanded_path.clone());
-
-            // Special handling for ~/* pattern
-            if path_str == "~/*" {
-                let subdirs = list_subdirectories(&expanded_path);
-                directories.extend(subdirs);
-            } 
-            // Normal recursive handling
-            else if recursive {
-                let subdirs = list_subdirectories(&expanded_path);
-                directories.extend(subdirs);
+
+            // Files created later under e.g. `~/Downloads/*.png` have to be picked up too
+            let file_name_is_glob = has_glob
+                && Path::new(&pattern)
+                    .file_name()
+                    .is_some_and(|name| is_glob(&name.to_string_lossy()));
+            if file_name_is_glob {
+                if let Ok(file_pattern) = Pattern::new(&pattern) {
+                    expanded.file_patterns.push(file_pattern);
+                }
+                if let Some(parent) = Path::new(&pattern).parent() {
+                    let parents = glob::glob_with(&parent.to_string_lossy(), GLOB_OPTIONS);
+                    expanded
+                        .parent_directories
+                        .extend(parents.into_iter().flatten().flatten().filter(|p| p.is_dir()));
+                }
             }
         }
+
+        if expanded.directories.len() + expanded.files.len() == matched_before {
+            expanded.warnings.push(format!("{}: matches no files or directories", path_str));
+        }
     }
 
-    directories
+    for list in [&mut expanded.directories, &mut expanded.files, &mut expanded.parent_directories] {
+        list.sort();
+        list.dedup();
+    }
+    expanded
 }
 
-fn list_subdirectories(base_path: &Path) -> Vec<PathBuf> {
-    let mut subdirs = Vec::new();
+/// Directories matched by the priority paths; see `expand_priority_paths`.
+pub fn expand_paths(paths: Vec<String>) -> Vec<PathBuf> {
+    expand_priority_paths(&paths).directories
+}
 
-    if let Ok(entries) = fs::read_dir(base_path) {
-        for entry in entries.flatten() {
-            let sub_path = entry.path();
-            if sub_path.is_dir() {
-                subdirs.push(sub_path.clone());
-            }
+/// Drops directories nested in another one of the list, which a recursive walk already covers.
+pub fn outermost_directories(mut directories: Vec<PathBuf>) -> Vec<PathBuf> {
+    directories.sort();
+    directories.dedup();
+
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for directory in directories {
+        if !roots.iter().any(|root| directory.starts_with(root)) {
+            roots.push(directory);
         }
     }
+    roots
+}
+
+fn is_glob(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
 
-    subdirs
+/// Escapes the longest leading part of an expanded pattern that exists on disk as written, so
+/// directory names containing `[`, `]`, `*` or `?` aren't taken for glob syntax. Also returns
+/// whether glob syntax remains after it.
+fn escape_existing_prefix(pattern: &str) -> (String, bool) {
+    if !is_glob(pattern) && !pattern.contains(']') {
+        return (pattern.to_string(), false);
+    }
+
+    let components: Vec<_> = Path::new(pattern).components().collect();
+    for len in (1..=components.len()).rev() {
+        let prefix: PathBuf = components[..len].iter().collect();
+        if !prefix.exists() {
+            continue;
+        }
+        let escaped = Pattern::escape(&prefix.to_string_lossy());
+        let rest: PathBuf = components[len..].iter().collect();
+        if rest.as_os_str().is_empty() {
+            return (escaped, false);
+        }
+        let rest = rest.to_string_lossy();
+        return (format!("{}/{}", escaped.trim_end_matches('/'), rest), is_glob(&rest));
+    }
+    (pattern.to_string(), true)
 }
 
-pub fn get_file_type(path: &Path) -> String {
-    match path.extension().and_then(|ext| ext.to_str()) {
-        Some("mp4") | Some("mkv") | Some("avi") => "video".to_string(),
-        Some("jpg") | Some("jpeg") | Some("png") | Some("gif") => "image".to_string(),
-        _ => "unknown".to_string(),
+/// Shell-style brace alternation: `a/{b,c}/d` becomes `a/b/d` and `a/c/d`. Nested groups are
+/// supported; a brace without a matching close is kept literally.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(open) = pattern.find('{') else {
+        return vec![pattern.to_string()];
+    };
+
+    let mut depth = 0;
+    let mut close = None;
+    let mut splits = Vec::new();
+    for (i, c) in pattern[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(open + i);
+                    break;
+                }
+            }
+            ',' if depth == 1 => splits.push(open + i),
+            _ => {}
+        }
     }
+
+    let Some(close) = close else {
+        return vec![pattern.to_string()];
+    };
+
+    let (prefix, suffix) = (&pattern[..open], &pattern[close + 1..]);
+    let mut bounds = vec![open];
+    bounds.extend(splits);
+    bounds.push(close);
+
+    bounds
+        .windows(2)
+        .flat_map(|window| {
+            let alternative = &pattern[window[0] + 1..window[1]];
+            expand_braces(&format!("{}{}{}", prefix, alternative, suffix))
+        })
+        .collect()
+}
+
+/// Media type of `path` by content, falling back to the extension; `None` for non-media files.
+pub fn get_file_type(path: &Path) -> Option<MediaType> {
+    media_kind::detect(path)
+}
+
+/// Modification time in nanoseconds since the epoch, the resolution stored by the caches.
+pub fn modified_nanos(metadata: &Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
 }
 
 #[tauri::command]
 pub async fn read_image(path: String) -> Result<Vec<u8>, String> {
+    // Members of ZIP/CBZ archives, e.g. `comic.cbz!/page_001.jpg`
+    if archive::is_member_path(Path::new(&path)) {
+        return archive::read_member(Path::new(&path)).map_err(|e| e.to_string());
+    }
     fs::read(path).map_err(|e| e.to_string())
 }
 
@@ -142,6 +289,8 @@ pub async fn reset_all(app: AppHandle) -> Result<bool, String> {
     match execute_command(&app, &mut command, "copy_resource".to_string()) {
         Ok(mut child) => match child.wait() {
             Ok(exit_status) if exit_status.success() => {
+                thumbnail_cache::clear();
+                catalog::clear().ok();
                 send_to_frontend(&app, "Removed All Cached Data".to_string(), "success_reset");
                 Ok(true)  // Return `true` on success
             }
@@ -167,4 +316,69 @@ pub async fn reset_all(app: AppHandle) -> Result<bool, String> {
 #[tauri::command]
 pub fn relaunch(app: tauri::AppHandle) {
     app.restart();
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand_braces_alternatives() {
+        assert_eq!(expand_braces("~/Pictures"), vec!["~/Pictures"]);
+        assert_eq!(expand_braces("a/{b,c}/d"), vec!["a/b/d", "a/c/d"]);
+        assert_eq!(expand_braces("{a,b}{1,2}"), vec!["a1", "a2", "b1", "b2"]);
+        assert_eq!(expand_braces("x/{a,{b,c}d}"), vec!["x/a", "x/bd", "x/cd"]);
+        assert_eq!(expand_braces("x/{}"), vec!["x/"]);
+    }
+
+    #[test]
+    fn expand_braces_keeps_unbalanced_braces() {
+        assert_eq!(expand_braces("a/{b,c"), vec!["a/{b,c"]);
+        assert_eq!(expand_braces("a/b}"), vec!["a/b}"]);
+    }
+
+    #[test]
+    fn outermost_directories_drops_nested() {
+        let directories = vec![
+            PathBuf::from("/home/u/Pictures/2024"),
+            PathBuf::from("/home/u/Pictures"),
+            PathBuf::from("/home/u/Pictures"),
+            PathBuf::from("/home/u/Pictures2"),
+            PathBuf::from("/home/u/Videos/clips"),
+        ];
+        assert_eq!(
+            outermost_directories(directories),
+            vec![
+                PathBuf::from("/home/u/Pictures"),
+                PathBuf::from("/home/u/Pictures2"),
+                PathBuf::from("/home/u/Videos/clips"),
+            ]
+        );
+        assert!(outermost_directories(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn literal_brackets_in_existing_directories() {
+        let root = temp_dir().join(format!("snaphound-glob-{}", Uuid::new_v4()));
+        let album = root.join("Album [2024]");
+        fs::create_dir_all(album.join("raw")).unwrap();
+        fs::write(album.join("a.png"), b"").unwrap();
+        fs::write(album.join("b.jpg"), b"").unwrap();
+
+        let album_str = album.to_string_lossy().into_owned();
+        let expanded = expand_priority_paths(std::slice::from_ref(&album_str));
+        assert_eq!(expanded.directories, vec![album.clone()]);
+        assert!(expanded.file_patterns.is_empty());
+
+        let expanded = expand_priority_paths(&[format!("{}/*.png", album_str)]);
+        assert_eq!(expanded.files, vec![album.join("a.png")]);
+        assert!(expanded.file_patterns[0].matches_path(&album.join("c.png")));
+        assert_eq!(expanded.parent_directories, vec![album.clone()]);
+
+        // Glob syntax after the part that exists still applies
+        let (pattern, has_glob) = escape_existing_prefix(&format!("{}/[ab].*", album_str));
+        assert!(has_glob);
+        assert!(Pattern::new(&pattern).unwrap().matches_path(&album.join("a.png")));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
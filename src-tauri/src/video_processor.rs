@@ -0,0 +1,185 @@
+use crate::heif::{self, Grid};
+use image::{DynamicImage, GenericImageView};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+// Fractions of the duration tried in order until a frame that is not (mostly) black turns up
+const SEEK_POINTS: [f64; 4] = [0.1, 0.25, 0.5, 0.75];
+// Mean luma below which a frame is treated as a black intro/fade frame
+const BLACK_FRAME_LUMA: f64 = 20.0;
+// Longest edge of the extracted frame; the WebP pipeline downsizes further
+const FRAME_MAX_EDGE: u32 = 1024;
+
+lazy_static::lazy_static! {
+    static ref FFMPEG_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Remembers the bundled ffmpeg resolved during initialisation.
+pub fn set_ffmpeg_path(path: PathBuf) {
+    *FFMPEG_PATH.lock().unwrap() = Some(path);
+}
+
+fn ffmpeg_binary() -> PathBuf {
+    FFMPEG_PATH
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(|path| path.exists())
+        // Fall back to whatever ffmpeg is on PATH, e.g. during development
+        .unwrap_or_else(|| PathBuf::from("ffmpeg"))
+}
+
+#[derive(Default)]
+struct VideoProbe {
+    duration: f64,
+    dimensions: Option<(u32, u32)>,
+}
+
+/// Grabs a representative frame of `video_path`, skipping black intro frames.
+/// Also returns the video's own dimensions, which the extracted frame may be scaled down from.
+pub fn extract_frame(video_path: &str) -> Result<(DynamicImage, (u32, u32)), String> {
+    let probe = probe_video(video_path).unwrap_or_default();
+    let mut fallback = None;
+    let mut last_error = format!("No frame could be extracted from {}", video_path);
+
+    // Without a known duration every seek point is 0; one attempt is all there is
+    let seek_points = if probe.duration > 0.0 { &SEEK_POINTS[..] } else { &SEEK_POINTS[..1] };
+    let frame = seek_points
+        .iter()
+        .find_map(|seek_point| match grab_frame(video_path, probe.duration * seek_point) {
+            Ok(frame) if mean_luma(&frame) >= BLACK_FRAME_LUMA => Some(frame),
+            Ok(frame) => {
+                fallback.get_or_insert(frame);
+                None
+            }
+            Err(e) => {
+                last_error = e;
+                None
+            }
+        })
+        // Every candidate was dark; a dark frame still beats no thumbnail
+        .or(fallback)
+        .ok_or(last_error)?;
+
+    let dimensions = probe.dimensions.unwrap_or_else(|| frame.dimensions());
+    Ok((frame, dimensions))
+}
+
+/// Decodes a still image `image` cannot read (HEIC, AVIF) through ffmpeg.
+pub fn decode_still(image_path: &str) -> Result<(DynamicImage, (u32, u32)), String> {
+    // ffmpeg decodes each tile of a grid image (most camera HEICs) as a stream of its own
+    if let Some(grid) = heif::grid(image_path).filter(|grid| grid.tiles.len() > 1) {
+        return grab_grid(image_path, &grid);
+    }
+
+    let frame = grab_frame(image_path, 0.0)?;
+    let dimensions = probe_video(image_path)
+        .and_then(|probe| probe.dimensions)
+        .filter(|(width, height)| *width >= frame.width() && *height >= frame.height())
+        .unwrap_or_else(|| frame.dimensions());
+    Ok((frame, dimensions))
+}
+
+/// Stacks the tiles of `grid` into the full image, then applies its rotation, which ffmpeg only
+/// knows for single-stream images.
+fn grab_grid(image_path: &str, grid: &Grid) -> Result<(DynamicImage, (u32, u32)), String> {
+    let inputs: String = grid.tiles.iter().map(|tile| format!("[0:i:{}]", tile)).collect();
+    let filter = format!(
+        "{}xstack=grid={}x{},crop={}:{}:0:0,{}",
+        inputs,
+        grid.columns,
+        grid.rows,
+        grid.width,
+        grid.height,
+        scale_filter()
+    );
+    let frame = run_ffmpeg(&["-i", image_path, "-filter_complex", &filter])
+        .map_err(|e| format!("ffmpeg could not assemble the image grid: {}", e))?;
+
+    Ok(match grid.rotation {
+        1 => (frame.rotate270(), (grid.height, grid.width)),
+        2 => (frame.rotate180(), (grid.width, grid.height)),
+        3 => (frame.rotate90(), (grid.height, grid.width)),
+        _ => (frame, (grid.width, grid.height)),
+    })
+}
+
+fn grab_frame(video_path: &str, seek_seconds: f64) -> Result<DynamicImage, String> {
+    // `thumbnail` picks the most representative frame out of the next batch of frames
+    let filter = format!("thumbnail=50,{}", scale_filter());
+    let seek = format!("{:.3}", seek_seconds);
+    run_ffmpeg(&["-ss", &seek, "-i", video_path, "-vf", &filter])
+        .map_err(|e| format!("ffmpeg could not extract a frame at {:.1}s: {}", seek_seconds, e))
+}
+
+fn scale_filter() -> String {
+    format!(
+        "scale='min({0},iw)':'min({0},ih)':force_original_aspect_ratio=decrease",
+        FRAME_MAX_EDGE
+    )
+}
+
+/// Runs ffmpeg with the input and filter `args` and decodes the first frame it outputs.
+fn run_ffmpeg(args: &[&str]) -> Result<DynamicImage, String> {
+    let output = Command::new(ffmpeg_binary())
+        .args(["-hide_banner", "-loglevel", "error"])
+        .args(args)
+        .args(["-frames:v", "1", "-f", "image2pipe", "-vcodec", "png", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+
+    if !output.status.success() || output.stdout.is_empty() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+
+    image::load_from_memory(&output.stdout).map_err(|e| e.to_string())
+}
+
+/// Reads the container duration and video size from ffmpeg's stream banner, e.g.
+/// `Duration: 00:01:23.45,` and `Stream #0:0: Video: h264 ..., 1920x1080 [SAR 1:1 DAR 16:9], ...`.
+fn probe_video(video_path: &str) -> Option<VideoProbe> {
+    let output = Command::new(ffmpeg_binary())
+        .args(["-hide_banner", "-i"])
+        .arg(video_path)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let duration = stderr
+        .split("Duration: ")
+        .nth(1)
+        .and_then(|rest| rest.split(',').next())
+        .and_then(|timestamp| {
+            timestamp
+                .trim()
+                .split(':')
+                .try_fold(0.0, |total, part| part.parse::<f64>().ok().map(|value| total * 60.0 + value))
+        })
+        .unwrap_or(0.0);
+
+    let dimensions = stderr
+        .lines()
+        .find(|line| line.contains("Video:"))
+        .and_then(|line| {
+            line.split([',', ' '])
+                .filter_map(|token| token.split_once('x'))
+                // Codec tags such as `0x31637661` share the shape, but never a non-zero width
+                .find_map(|(w, h)| {
+                    Some((w.parse::<u32>().ok().filter(|w| *w > 0)?, h.parse::<u32>().ok()?))
+                })
+        });
+
+    Some(VideoProbe { duration, dimensions })
+}
+
+fn mean_luma(frame: &DynamicImage) -> f64 {
+    // Sample a small copy; exact precision is not needed to spot a black frame
+    let sample = frame.thumbnail(64, 64).to_luma8();
+    let (width, height) = sample.dimensions();
+    let pixel_count = (width * height).max(1) as f64;
+
+    sample.pixels().map(|p| p[0] as f64).sum::<f64>() / pixel_count
+}
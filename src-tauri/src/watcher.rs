@@ -0,0 +1,394 @@
+use crate::archive;
+use crate::catalog;
+use crate::exclusion::ExclusionRules;
+use crate::file_processor::build_media_item;
+use crate::media_kind::MediaType;
+use crate::scan_settings::RootSettings;
+use crate::search_processor::send_command_to_python;
+use crate::utils::{
+    expand_priority_paths, get_file_type, outermost_directories, send_to_frontend, ExpandedPaths,
+};
+use notify_debouncer_full::notify::event::{ModifyKind, RenameMode};
+use notify_debouncer_full::notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use notify_debouncer_full::{new_debouncer, DebounceEventResult, Debouncer, FileIdMap};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::AppHandle;
+
+// Bursts (e.g. a browser writing a download in pieces) settle into one event per file
+const DEBOUNCE_TIMEOUT: Duration = Duration::from_millis(750);
+
+lazy_static::lazy_static! {
+    static ref WATCHER: Mutex<Option<Debouncer<RecommendedWatcher, FileIdMap>>> = Mutex::new(None);
+}
+
+#[derive(Default)]
+struct ChangeSet {
+    added: Vec<PathBuf>,
+    removed: Vec<PathBuf>,
+    renamed: Vec<(PathBuf, PathBuf)>,
+    // Directories that appeared, e.g. moved into a root; their files count as added
+    added_directories: Vec<PathBuf>,
+}
+
+/// (Re)starts watching the enabled priority paths, replacing any previous watcher.
+pub fn start_watching(app: AppHandle, roots: Vec<RootSettings>, rules: Arc<ExclusionRules>) {
+    stop_watching();
+
+    let watched: Arc<Vec<(RootSettings, ExpandedPaths)>> = Arc::new(
+        roots
+            .into_iter()
+            .map(|root| {
+                let expanded = expand_priority_paths(std::slice::from_ref(&root.path));
+                (root, expanded)
+            })
+            .collect(),
+    );
+    let app_clone = app.clone();
+    let handler_rules = Arc::clone(&rules);
+    let handler_roots = Arc::clone(&watched);
+    let mut debouncer = match new_debouncer(DEBOUNCE_TIMEOUT, None, move |result: DebounceEventResult| {
+        match result {
+            Ok(events) => {
+                let changes = collect_changes(events.iter().map(|e| &e.event), &handler_rules);
+                handle_changes(&app_clone, filter_changes(changes, &handler_roots, &handler_rules));
+            }
+            Err(errors) => errors
+                .iter()
+                .for_each(|e| println!("File watcher error: {:?}", e)),
+        }
+    }) {
+        Ok(debouncer) => debouncer,
+        Err(e) => {
+            send_to_frontend(&app, format!("Failed to start file watcher: {}", e), "error");
+            return;
+        }
+    };
+
+    for (root, expanded) in watched.iter() {
+        let mode = if root.is_recursive() {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+
+        let directories: Vec<PathBuf> = expanded
+            .directories
+            .iter()
+            .chain(&expanded.parent_directories)
+            .filter(|directory| !rules.is_excluded(directory, true))
+            .cloned()
+            .collect();
+        let directories = if root.is_recursive() {
+            outermost_directories(directories)
+        } else {
+            directories
+        };
+        for directory in directories {
+            if let Err(e) = debouncer.watcher().watch(&directory, mode) {
+                println!("Failed to watch {:?}: {}", directory, e);
+                continue;
+            }
+            debouncer.cache().add_root(&directory, mode);
+        }
+    }
+
+    *WATCHER.lock().unwrap() = Some(debouncer);
+}
+
+pub fn stop_watching() {
+    // Dropping the debouncer stops its thread and releases the inotify watches
+    WATCHER.lock().unwrap().take();
+}
+
+fn collect_changes<'a>(
+    events: impl Iterator<Item = &'a notify_debouncer_full::notify::Event>,
+    rules: &ExclusionRules,
+) -> ChangeSet {
+    let mut changes = ChangeSet::default();
+
+    for event in events {
+        // Recursive watches still see excluded directories; drop their events here
+        if event.paths.iter().all(|path| rules.is_excluded(path, path.is_dir())) {
+            continue;
+        }
+
+        match event.kind {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                changes.renamed.push((event.paths[0].clone(), event.paths[1].clone()));
+            }
+            EventKind::Remove(_) => {
+                changes.removed.extend(event.paths.iter().cloned());
+            }
+            // Permission or timestamp changes alone don't affect the thumbnail
+            EventKind::Modify(ModifyKind::Metadata(_)) => {}
+            EventKind::Create(_) | EventKind::Modify(_) => {
+                // One-sided renames only tell us whether the path exists afterwards
+                for path in &event.paths {
+                    if path.is_file() {
+                        changes.added.push(path.clone());
+                    } else if path.is_dir() {
+                        changes.added_directories.push(path.clone());
+                    } else if !path.exists() {
+                        changes.removed.push(path.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    changes.added = unique(std::mem::take(&mut changes.added));
+    changes.removed = unique(std::mem::take(&mut changes.removed));
+    changes
+}
+
+/// Whether a scan would catalogue `path`. Patterns like `~/Downloads/*.png` watch a directory
+/// but only want some of its files, and roots may limit depth or media types.
+fn wanted(path: &Path, watched: &[(RootSettings, ExpandedPaths)], rules: &ExclusionRules) -> bool {
+    !rules.is_excluded(path, false)
+        && watched.iter().any(|(root, expanded)| {
+            let allowed = if archive::is_archive(path) {
+                root.allows_type("image")
+            } else {
+                get_file_type(path).is_some_and(|media| root.allows_type(media.category()))
+            };
+            allowed && expanded.contains(path, root)
+        })
+}
+
+/// Applies the scan's rules to a change set. A renamed directory becomes the removal of its old
+/// path, which takes its catalogued files along, and an added directory that is walked like a
+/// scan would. Files, and rename targets, that a scan would skip are dropped; renaming a file
+/// into one of them removes it.
+fn filter_changes(
+    mut changes: ChangeSet,
+    watched: &[(RootSettings, ExpandedPaths)],
+    rules: &Arc<ExclusionRules>,
+) -> ChangeSet {
+    for (from, to) in std::mem::take(&mut changes.renamed) {
+        if to.is_dir() {
+            changes.removed.push(from);
+            changes.added_directories.push(to);
+        } else if wanted(&to, watched, rules) {
+            changes.renamed.push((from, to));
+        } else {
+            changes.removed.push(from);
+        }
+    }
+
+    for directory in std::mem::take(&mut changes.added_directories) {
+        let root = watched.iter().find(|(_, expanded)| {
+            expanded
+                .directories
+                .iter()
+                .chain(&expanded.parent_directories)
+                .any(|watched_directory| directory.starts_with(watched_directory))
+        });
+        if let Some((root, _)) = root.filter(|_| !rules.is_excluded(&directory, true)) {
+            changes.added.extend(
+                ExclusionRules::walker(rules, &directory, root)
+                    .flatten()
+                    .map(|entry| entry.into_path())
+                    .filter(|path| path.is_file()),
+            );
+        }
+    }
+
+    changes.added.retain(|path| wanted(path, watched, rules));
+    changes.added = unique(std::mem::take(&mut changes.added));
+    changes.removed = unique(std::mem::take(&mut changes.removed));
+    changes
+}
+
+fn unique<T: Ord>(mut items: Vec<T>) -> Vec<T> {
+    items.sort();
+    items.dedup();
+    items
+}
+
+// Removed files can no longer be sniffed; the catalog knows whether they were media
+fn was_media(path: &Path) -> bool {
+    catalog::get(&path.to_string_lossy()).is_some() || get_file_type(path).is_some()
+}
+
+fn handle_changes(app: &AppHandle, mut changes: ChangeSet) {
+    // Archives are listed again as a whole, so a renamed one is removed and added
+    let (archive_renames, renamed) = std::mem::take(&mut changes.renamed)
+        .into_iter()
+        .partition::<Vec<_>, _>(|(from, to)| archive::is_archive(from) || archive::is_archive(to));
+    changes.renamed = renamed;
+    for (from, to) in archive_renames {
+        changes.removed.push(from);
+        if to.is_file() {
+            changes.added.push(to);
+        }
+    }
+
+    let mut added: Vec<(PathBuf, MediaType)> = Vec::new();
+    let mut removed: Vec<String> = Vec::new();
+    for path in &changes.added {
+        if archive::is_archive(path) {
+            let members = archive::list_members(path).unwrap_or_default();
+            // Pages dropped from a rewritten archive
+            removed.extend(archive::catalogued_members(path).into_iter().filter(|catalogued| {
+                !members.iter().any(|member| member.path.to_string_lossy() == catalogued.as_str())
+            }));
+            added.extend(members.into_iter().map(|member| (member.path, member.media)));
+        } else if let Some(media) = get_file_type(path) {
+            added.push((path.clone(), media));
+        }
+    }
+    for path in &changes.removed {
+        if archive::is_archive(path) {
+            removed.extend(archive::catalogued_members(path));
+        } else if was_media(path) {
+            removed.push(path.to_string_lossy().into_owned());
+        } else {
+            // A removed or moved-away directory takes its catalogued files along
+            removed.extend(catalog::paths_with_prefix(&format!("{}{}", path.to_string_lossy(), MAIN_SEPARATOR)));
+        }
+    }
+
+    // Archive members reach the indexer through `archive::sync_indexer` instead
+    let mut to_index: Vec<String> = added
+        .iter()
+        .filter(|(p, _)| !archive::is_member_path(p))
+        .map(|(p, _)| p.to_string_lossy().into_owned())
+        .collect();
+    let mut to_unindex: Vec<String> = removed
+        .iter()
+        .filter(|p| !archive::is_member_path(Path::new(p)))
+        .cloned()
+        .collect();
+    let members_changed = added.len() + removed.len() > to_index.len() + to_unindex.len();
+
+    if !added.is_empty() {
+        let items: Vec<Value> = added
+            .iter()
+            .map(|(path, media)| build_media_item(app, path, media))
+            .collect();
+        send_to_frontend(app, json!(items).to_string(), "file_added");
+    }
+
+    if !removed.is_empty() {
+        if let Err(e) = catalog::remove(&removed) {
+            println!("Failed to update catalog: {}", e);
+        }
+        send_to_frontend(app, json!(removed).to_string(), "file_removed");
+    }
+
+    let mut renamed = Vec::new();
+    for (from, to) in &changes.renamed {
+        let from_str = from.to_string_lossy().into_owned();
+
+        // A rename may also change the extension, in or out of the media types. The new item
+        // is built first so it can take over the catalog id of the old path.
+        let item = match get_file_type(to).filter(|_| to.is_file()) {
+            Some(media) => {
+                to_index.push(to.to_string_lossy().into_owned());
+                Some(build_media_item(app, to, &media))
+            }
+            None => None,
+        };
+
+        let from_media = was_media(from);
+        if from_media {
+            catalog::remove(std::slice::from_ref(&from_str)).ok();
+            to_unindex.push(from_str.clone());
+        }
+
+        if from_media || item.is_some() {
+            renamed.push(json!({ "from": from_str, "to": to, "item": item }));
+        }
+    }
+    if !renamed.is_empty() {
+        send_to_frontend(app, json!(renamed).to_string(), "file_renamed");
+    }
+
+    // Tell the indexer about exactly the files that changed, never a full reindex
+    if !to_unindex.is_empty() {
+        send_command_to_python(&json!({ "unindex_files": unique(to_unindex) }).to_string());
+    }
+    if !to_index.is_empty() {
+        send_command_to_python(&json!({ "index_files": unique(to_index) }).to_string());
+    }
+    if members_changed {
+        archive::sync_indexer(
+            app,
+            added
+                .iter()
+                .filter(|(p, _)| archive::is_member_path(p))
+                .filter_map(|(p, _)| p.to_str()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs;
+    use uuid::Uuid;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    fn watched(root: &Path) -> Vec<(RootSettings, ExpandedPaths)> {
+        let root: RootSettings = serde_json::from_value(json!({ "path": root })).unwrap();
+        let expanded = expand_priority_paths(std::slice::from_ref(&root.path));
+        vec![(root, expanded)]
+    }
+
+    fn rules(root: &Path) -> Arc<ExclusionRules> {
+        Arc::new(ExclusionRules::from_config(&json!({ "priority_paths": [root], "recursive": true })))
+    }
+
+    #[test]
+    fn moved_directory_is_removed_and_walked() {
+        let root = temp_dir().join(format!("snaphound-watch-{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("album/nested")).unwrap();
+        fs::write(root.join("album/a.png"), PNG).unwrap();
+        fs::write(root.join("album/nested/b.png"), PNG).unwrap();
+        fs::write(root.join("album/notes.txt"), b"").unwrap();
+        fs::rename(root.join("album"), root.join("album 2")).unwrap();
+
+        let rules = rules(&root);
+        let changes = ChangeSet {
+            renamed: vec![(root.join("album"), root.join("album 2"))],
+            ..ChangeSet::default()
+        };
+        let changes = filter_changes(changes, &watched(&root), &rules);
+        assert!(changes.renamed.is_empty());
+        assert_eq!(changes.removed, vec![root.join("album")]);
+        assert_eq!(
+            changes.added,
+            vec![root.join("album 2/a.png"), root.join("album 2/nested/b.png")]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn renames_into_excluded_paths_become_removals() {
+        let root = temp_dir().join(format!("snaphound-watch-{}", Uuid::new_v4()));
+        fs::create_dir_all(root.join("node_modules")).unwrap();
+        fs::write(root.join("node_modules/a.png"), PNG).unwrap();
+        fs::write(root.join("b.png"), PNG).unwrap();
+
+        let rules = rules(&root);
+        let changes = ChangeSet {
+            renamed: vec![
+                (root.join("a.png"), root.join("node_modules/a.png")),
+                (root.join("c.png"), root.join("b.png")),
+            ],
+            ..ChangeSet::default()
+        };
+        let changes = filter_changes(changes, &watched(&root), &rules);
+        assert_eq!(changes.removed, vec![root.join("a.png")]);
+        assert_eq!(changes.renamed, vec![(root.join("c.png"), root.join("b.png"))]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}